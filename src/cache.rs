@@ -0,0 +1,278 @@
+//! Finished scans saved to the user's cache directory, so reopening a large
+//! root doesn't have to walk it again.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::core::{Completeness, DirEntryInfo, ScanOptions, ScanResult, SizeMode, SizePolicy};
+
+/// Bumped whenever the saved layout changes, so old files are ignored.
+const CACHE_VERSION: u32 = 4;
+
+/// `$XDG_CACHE_HOME/disk-usage-tui`, falling back to `~/.cache` (or
+/// `%LOCALAPPDATA%` on Windows).
+pub fn cache_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("LOCALAPPDATA").map(PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+    Some(base.join("disk-usage-tui"))
+}
+
+/// One file per root, named after a hash of its path. The name has to
+/// come out the same from every build, so it's FNV-1a over the path's
+/// bytes rather than std's hasher, which is free to change. Two roots
+/// sharing a name just miss, since the file records its root.
+fn cache_file(root: &Path) -> Option<PathBuf> {
+    cache_dir().map(|dir| dir.join(cache_file_name(root)))
+}
+
+fn cache_file_name(root: &Path) -> String {
+    #[cfg(unix)]
+    let bytes = std::os::unix::ffi::OsStrExt::as_bytes(root.as_os_str());
+    #[cfg(not(unix))]
+    let lossy = root.to_string_lossy();
+    #[cfg(not(unix))]
+    let bytes = lossy.as_bytes();
+    format!("{:016x}.json", fnv1a(bytes))
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// The options that change what a scan finds, spelled out field by field
+/// so the key doesn't shift with a `Debug` impl. Changing the layout means
+/// bumping `CACHE_VERSION`. Limits only matter for partial scans, which
+/// aren't cached, and the thread count doesn't change the result.
+fn options_key(options: &ScanOptions) -> String {
+    // No `..`, so a new option can't be left out of the key unnoticed
+    let ScanOptions {
+        follow_symlinks,
+        contain_symlinks,
+        size_mode,
+        precise_sparse,
+        size_policy,
+        include,
+        exclude,
+        use_gitignore,
+        one_file_system,
+        timeout: _,
+        max_entries: _,
+        cluster_size,
+        max_depth,
+        threads: _,
+        xattrs,
+        cancel: _,
+    } = options;
+    let size_mode = match size_mode {
+        SizeMode::Allocated => "allocated",
+        SizeMode::Apparent => "apparent",
+    };
+    let SizePolicy {
+        symlinks,
+        dir_overhead,
+        special,
+    } = size_policy;
+    let globs = |globs: &[String]| serde_json::to_string(globs).unwrap_or_default();
+    format!(
+        "follow_symlinks={follow_symlinks};contain_symlinks={contain_symlinks};\
+         size_mode={size_mode};precise_sparse={precise_sparse};\
+         policy_symlinks={symlinks};policy_dir_overhead={dir_overhead};policy_special={special};\
+         include={};exclude={};use_gitignore={use_gitignore};one_file_system={one_file_system};\
+         cluster_size={};max_depth={};xattrs={xattrs}",
+        globs(include),
+        globs(exclude),
+        optional(cluster_size),
+        optional(max_depth),
+    )
+}
+
+fn optional(value: &Option<impl ToString>) -> String {
+    value
+        .as_ref()
+        .map_or("none".to_string(), ToString::to_string)
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile<'a> {
+    version: u32,
+    root: PathBuf,
+    options: String,
+    scanned: SystemTime,
+    /// The root's modification time when saved; adding or removing one of
+    /// its entries changes it, which makes the saved tree stale.
+    root_modified: Option<SystemTime>,
+    tree: Cow<'a, DirEntryInfo>,
+    completeness: Cow<'a, Completeness>,
+}
+
+/// A scan read back from the cache.
+pub struct CachedScan {
+    /// When the scan finished.
+    pub scanned: SystemTime,
+    /// The tree and completeness as saved. The unreadable entries
+    /// themselves aren't kept, only their count.
+    pub scan: ScanResult,
+}
+
+fn root_modified(root: &Path) -> Option<SystemTime> {
+    std::fs::metadata(root).and_then(|md| md.modified()).ok()
+}
+
+/// The saved scan of `root`, if there is one made with the same options
+/// since the root last changed.
+pub fn load(root: &Path, options: &ScanOptions) -> Option<CachedScan> {
+    load_from(&cache_file(root)?, root, options)
+}
+
+fn load_from(path: &Path, root: &Path, options: &ScanOptions) -> Option<CachedScan> {
+    let contents = std::fs::read(path).ok()?;
+    let file: CacheFile = serde_json::from_slice(&contents).ok()?;
+    if file.version != CACHE_VERSION
+        || file.root != root
+        || file.options != options_key(options)
+        || file.root_modified.is_none()
+        || file.root_modified != root_modified(root)
+    {
+        return None;
+    }
+    Some(CachedScan {
+        scanned: file.scanned,
+        scan: ScanResult {
+            tree: file.tree.into_owned(),
+            completeness: file.completeness.into_owned(),
+            errors: Vec::new(),
+            scan_times: HashMap::new(),
+        },
+    })
+}
+
+/// Saves `scan` of `root`, replacing any earlier one. Does nothing without
+/// a cache directory.
+pub fn save(root: &Path, options: &ScanOptions, scan: &ScanResult) -> std::io::Result<()> {
+    match cache_file(root) {
+        Some(path) => save_to(&path, root, options, scan),
+        None => Ok(()),
+    }
+}
+
+fn save_to(
+    path: &Path,
+    root: &Path,
+    options: &ScanOptions,
+    scan: &ScanResult,
+) -> std::io::Result<()> {
+    let file = CacheFile {
+        version: CACHE_VERSION,
+        root: root.to_path_buf(),
+        options: options_key(options),
+        scanned: SystemTime::now(),
+        root_modified: root_modified(root),
+        tree: Cow::Borrowed(&scan.tree),
+        completeness: Cow::Borrowed(&scan.completeness),
+    };
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // Write beside the old file and swap, so an interrupted save doesn't
+    // leave a broken cache behind
+    let partial = path.with_extension("json.tmp");
+    let mut out = std::io::BufWriter::new(std::fs::File::create(&partial)?);
+    serde_json::to_writer(&mut out, &file)?;
+    out.flush()?;
+    drop(out);
+    std::fs::rename(partial, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::build_tree;
+    use crate::test_support::TempDir;
+
+    fn scan(dir: &TempDir) -> ScanResult {
+        let pb = indicatif::ProgressBar::hidden();
+        build_tree(dir.path(), &ScanOptions::default(), &pb).unwrap()
+    }
+
+    fn json(tree: &DirEntryInfo) -> serde_json::Value {
+        serde_json::to_value(tree).unwrap()
+    }
+
+    #[test]
+    fn round_trips_a_scan() {
+        let dir = TempDir::new("cache-round-trip");
+        dir.file("sub/a", 10);
+        dir.file("b", 3000);
+        let saved = scan(&dir);
+        let store = TempDir::new("cache-round-trip-store");
+        let path = store.path().join("scan.json");
+        save_to(&path, dir.path(), &ScanOptions::default(), &saved).unwrap();
+
+        let loaded = load_from(&path, dir.path(), &ScanOptions::default()).unwrap();
+        assert_eq!(json(&loaded.scan.tree), json(&saved.tree));
+        assert!(loaded.scan.completeness.is_exact());
+    }
+
+    #[test]
+    fn misses_on_other_options_or_a_changed_root() {
+        let dir = TempDir::new("cache-stale");
+        dir.file("a", 10);
+        let store = TempDir::new("cache-stale-store");
+        let path = store.path().join("scan.json");
+        save_to(&path, dir.path(), &ScanOptions::default(), &scan(&dir)).unwrap();
+
+        let apparent = ScanOptions {
+            size_mode: SizeMode::Apparent,
+            ..ScanOptions::default()
+        };
+        assert!(load_from(&path, dir.path(), &apparent).is_none());
+        // Threads don't change what a scan finds
+        let threads = ScanOptions {
+            threads: Some(1),
+            ..ScanOptions::default()
+        };
+        assert!(load_from(&path, dir.path(), &threads).is_some());
+
+        dir.file("b", 10);
+        assert!(load_from(&path, dir.path(), &ScanOptions::default()).is_none());
+    }
+
+    #[test]
+    fn file_names_are_stable_across_builds() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(
+            cache_file_name(Path::new("/home/user")),
+            "8f4b200009ba673d.json"
+        );
+    }
+
+    #[test]
+    fn options_key_spells_out_each_option() {
+        assert_eq!(
+            options_key(&ScanOptions::default()),
+            "follow_symlinks=false;contain_symlinks=false;size_mode=allocated;\
+             precise_sparse=false;policy_symlinks=false;policy_dir_overhead=false;\
+             policy_special=false;include=[];exclude=[];use_gitignore=false;\
+             one_file_system=false;cluster_size=none;max_depth=none;xattrs=false"
+        );
+        let options = ScanOptions {
+            include: vec!["*.rs".to_string()],
+            max_depth: Some(2),
+            ..ScanOptions::default()
+        };
+        let key = options_key(&options);
+        assert!(key.contains("include=[\"*.rs\"]"), "{}", key);
+        assert!(key.contains("max_depth=2"), "{}", key);
+    }
+}
@@ -0,0 +1,125 @@
+//! Preferences and scan history remembered between runs, kept as small
+//! text files in the user's config directory.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::core::units::UnitSystem;
+
+/// Unit system chosen per scanned root, one `<units>\t<path>` line each.
+const UNITS_FILE: &str = "units";
+
+/// Completed scans, one `<unix seconds>\t<total bytes>\t<path>` line each,
+/// oldest first.
+const HISTORY_FILE: &str = "history";
+
+/// Scans kept in the history; older ones are dropped.
+const HISTORY_LIMIT: usize = 1000;
+
+/// `$XDG_CONFIG_HOME/disk-usage-tui`, falling back to `~/.config` (or
+/// `%APPDATA%` on Windows).
+pub fn config_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(base.join("disk-usage-tui"))
+}
+
+fn read_units() -> BTreeMap<PathBuf, UnitSystem> {
+    let Some(file) = config_dir().map(|dir| dir.join(UNITS_FILE)) else {
+        return BTreeMap::new();
+    };
+    let contents = std::fs::read_to_string(file).unwrap_or_default();
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (units, path) = line.split_once('\t')?;
+            Some((PathBuf::from(path), UnitSystem::from_name(units)?))
+        })
+        .collect()
+}
+
+/// The unit system last picked for `root`, if any.
+pub fn load_units(root: &Path) -> Option<UnitSystem> {
+    read_units().remove(root)
+}
+
+/// Remembers `units` for `root`, keeping the choices for other roots.
+pub fn save_units(root: &Path, units: UnitSystem) -> std::io::Result<()> {
+    let dir = config_dir()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no config directory"))?;
+    let mut saved = read_units();
+    saved.insert(root.to_path_buf(), units);
+
+    let mut contents = String::new();
+    for (path, units) in &saved {
+        contents.push_str(&format!("{}\t{}\n", units.name(), path.display()));
+    }
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(dir.join(UNITS_FILE), contents)
+}
+
+/// One completed scan in the history.
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub time: SystemTime,
+    pub size: u64,
+    pub root: PathBuf,
+}
+
+/// Every recorded scan, oldest first.
+pub fn load_history() -> Vec<HistoryEntry> {
+    let Some(file) = config_dir().map(|dir| dir.join(HISTORY_FILE)) else {
+        return Vec::new();
+    };
+    let contents = std::fs::read_to_string(file).unwrap_or_default();
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\t');
+            let secs = fields.next()?.parse().ok()?;
+            let size = fields.next()?.parse().ok()?;
+            Some(HistoryEntry {
+                time: SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+                size,
+                root: PathBuf::from(fields.next()?),
+            })
+        })
+        .collect()
+}
+
+/// Appends a scan of `root` totalling `size` bytes, trimming the oldest
+/// entries past the limit. Does nothing without a config directory.
+pub fn record_scan(root: &Path, size: u64) -> std::io::Result<()> {
+    let Some(dir) = config_dir() else {
+        return Ok(());
+    };
+    let secs = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let line = format!("{}\t{}\t{}", secs, size, root.display());
+
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join(HISTORY_FILE);
+    let contents = std::fs::read_to_string(&path).unwrap_or_default();
+    let mut lines: Vec<&str> = contents.lines().collect();
+    lines.push(&line);
+    let start = lines.len().saturating_sub(HISTORY_LIMIT);
+    let mut kept = lines[start..].join("\n");
+    kept.push('\n');
+    std::fs::write(path, kept)
+}
+
+/// Forgets every recorded scan.
+pub fn clear_history() -> std::io::Result<()> {
+    let Some(file) = config_dir().map(|dir| dir.join(HISTORY_FILE)) else {
+        return Ok(());
+    };
+    match std::fs::remove_file(file) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,173 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+
+use super::volume;
+
+/// What `remove_tree` sends while it runs.
+pub enum DeleteUpdate {
+    /// An entry is gone from disk. A file with `still_linked` set had other
+    /// names, which keep its data and the space it takes.
+    Removed { path: PathBuf, still_linked: bool },
+    /// The removal is over; nothing is sent after this.
+    Done(DeleteOutcome),
+}
+
+#[derive(Debug, Default)]
+pub struct DeleteOutcome {
+    /// Stopped through the cancel flag before everything was removed.
+    pub cancelled: bool,
+    /// How many entries couldn't be removed, and the first reason why.
+    pub failures: usize,
+    pub first_error: Option<io::Error>,
+}
+
+/// Removes `path` and everything below it, children before their
+/// directory, telling `tx` about each entry as it goes. Links are removed
+/// as links, never followed. Entries that can't be removed are counted and
+/// skipped, as are the directories left holding them. Checks `cancel`
+/// before every entry.
+pub fn remove_tree(path: &Path, cancel: &AtomicBool, tx: Sender<DeleteUpdate>) {
+    let mut outcome = DeleteOutcome::default();
+    let walk = walkdir::WalkDir::new(path)
+        .follow_root_links(false)
+        .contents_first(true);
+    for entry in walk {
+        if cancel.load(Ordering::Relaxed) {
+            outcome.cancelled = true;
+            break;
+        }
+        let removed = entry.map_err(io::Error::from).and_then(|entry| {
+            let mut still_linked = false;
+            if entry.file_type().is_dir() {
+                std::fs::remove_dir(entry.path())?;
+            } else {
+                still_linked = entry
+                    .metadata()
+                    .ok()
+                    .and_then(|md| volume::hardlink(&md))
+                    .is_some();
+                std::fs::remove_file(entry.path())?;
+            }
+            Ok(DeleteUpdate::Removed {
+                path: entry.into_path(),
+                still_linked,
+            })
+        });
+        match removed {
+            Ok(update) => {
+                // Keep going if the receiver left; the user asked for this
+                let _ = tx.send(update);
+            }
+            Err(err) => {
+                outcome.failures += 1;
+                outcome.first_error.get_or_insert(err);
+            }
+        }
+    }
+    let _ = tx.send(DeleteUpdate::Done(outcome));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+    use std::sync::mpsc;
+
+    fn run(path: &Path, cancel: &AtomicBool) -> (Vec<PathBuf>, DeleteOutcome) {
+        let (removed, outcome) = run_linked(path, cancel);
+        (removed.into_iter().map(|(path, _)| path).collect(), outcome)
+    }
+
+    fn run_linked(path: &Path, cancel: &AtomicBool) -> (Vec<(PathBuf, bool)>, DeleteOutcome) {
+        let (tx, rx) = mpsc::channel();
+        remove_tree(path, cancel, tx);
+        let mut removed = Vec::new();
+        for update in rx {
+            match update {
+                DeleteUpdate::Removed { path, still_linked } => removed.push((path, still_linked)),
+                DeleteUpdate::Done(outcome) => return (removed, outcome),
+            }
+        }
+        panic!("no outcome sent");
+    }
+
+    #[test]
+    fn removes_children_before_their_directory() {
+        let dir = TempDir::new("remove-tree");
+        dir.file("doomed/a/one", 1);
+        dir.file("doomed/two", 2);
+        let doomed = dir.path().join("doomed");
+
+        let (removed, outcome) = run(&doomed, &AtomicBool::new(false));
+        assert!(!doomed.exists());
+        assert_eq!(removed.len(), 4);
+        assert_eq!(removed.last(), Some(&doomed));
+        let a = removed.iter().position(|p| p.ends_with("a")).unwrap();
+        let one = removed.iter().position(|p| p.ends_with("one")).unwrap();
+        assert!(one < a);
+        assert!(!outcome.cancelled);
+        assert_eq!(outcome.failures, 0);
+    }
+
+    #[test]
+    fn removes_a_single_file() {
+        let dir = TempDir::new("remove-file");
+        let file = dir.file("lone", 3);
+        let (removed, _) = run(&file, &AtomicBool::new(false));
+        assert_eq!(removed, vec![file.clone()]);
+        assert!(!file.exists());
+    }
+
+    #[test]
+    fn cancelling_keeps_what_is_left() {
+        let dir = TempDir::new("remove-cancelled");
+        dir.file("kept/file", 1);
+        let kept = dir.path().join("kept");
+
+        let (removed, outcome) = run(&kept, &AtomicBool::new(true));
+        assert!(removed.is_empty());
+        assert!(outcome.cancelled);
+        assert!(kept.join("file").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_are_removed_not_followed() {
+        let dir = TempDir::new("remove-link");
+        let target = dir.file("target/keep", 1);
+        let link = dir.path().join("doomed/link");
+        std::fs::create_dir(dir.path().join("doomed")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("target"), &link).unwrap();
+
+        run(&dir.path().join("doomed"), &AtomicBool::new(false));
+        assert!(link.symlink_metadata().is_err());
+        assert!(target.exists());
+
+        // Nor when the link itself is what's deleted
+        let root_link = dir.path().join("root-link");
+        std::os::unix::fs::symlink(dir.path().join("target"), &root_link).unwrap();
+        run(&root_link, &AtomicBool::new(false));
+        assert!(root_link.symlink_metadata().is_err());
+        assert!(target.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reports_files_that_keep_other_names() {
+        let dir = TempDir::new("remove-hardlinks");
+        let first = dir.file("doomed/first", 10);
+        let second = dir.path().join("doomed/second");
+        std::fs::hard_link(&first, &second).unwrap();
+
+        let (removed, _) = run_linked(&dir.path().join("doomed"), &AtomicBool::new(false));
+        let linked: Vec<bool> = removed
+            .iter()
+            .filter(|(path, _)| path != &dir.path().join("doomed"))
+            .map(|&(_, linked)| linked)
+            .collect();
+        // Whichever goes first leaves the other as the last name
+        assert_eq!(linked, [true, false]);
+    }
+}
@@ -1,81 +1,1368 @@
+pub mod delete;
+pub mod priority;
+pub mod size;
+pub mod sort;
+pub mod units;
+pub mod volume;
+pub mod walk;
+
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::{sync_channel, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
-use ignore::WalkBuilder;
 use indicatif::ProgressBar;
-use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+use walk::{WalkOutcome, WalkedEntry};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DirEntryInfo {
     pub path: PathBuf,
     pub size: u64,
+    /// Bytes allocated on disk; below `size` for sparse or compressed files.
+    pub allocated_size: u64,
+    /// `size` with each file rounded up to the volume's allocation unit, or
+    /// the plain size when `ScanOptions::cluster_size` isn't set.
+    pub rounded_size: u64,
     pub is_dir: bool,
+    /// Number of entries (files and directories) in this subtree, itself included.
+    pub inodes: u64,
+    /// Number of files in this subtree; 1 for a file.
+    pub file_count: u64,
+    pub modified: Option<SystemTime>,
+    /// Latest `modified` in this subtree, itself included.
+    pub newest_modified: Option<SystemTime>,
+    /// A directory on a different filesystem than its parent.
+    pub mount_point: bool,
+    /// Set for files with more than one hard link.
+    pub hardlink: Option<Hardlink>,
+    /// A followed link back to a directory above it, left empty rather
+    /// than walked again.
+    pub symlink_loop: bool,
     pub children: Vec<DirEntryInfo>,
 }
 
+/// Identity of a file shared by all of its hard links.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Hardlink {
+    pub device: u64,
+    pub inode: u64,
+    /// Number of names the file has, inside the scan or not.
+    pub links: u64,
+    /// Whether this name carries the file's size. Only the first name in
+    /// path order does, so the file counts once.
+    pub counted: bool,
+}
+
+/// Entries with less than this share of their size on disk are flagged.
+const SPARSE_RATIO: f64 = 0.75;
+
+impl DirEntryInfo {
+    /// Share of `size` actually allocated on disk, when it's notably less
+    /// than the full size (sparse or compressed data).
+    pub fn allocation_ratio(&self) -> Option<f64> {
+        if self.size == 0 {
+            return None;
+        }
+        let ratio = self.allocated_size as f64 / self.size as f64;
+        (ratio < SPARSE_RATIO).then_some(ratio)
+    }
+
+    /// A copy without the children, for listings that only show this
+    /// entry's own totals.
+    pub fn without_children(&self) -> DirEntryInfo {
+        DirEntryInfo {
+            path: self.path.clone(),
+            size: self.size,
+            allocated_size: self.allocated_size,
+            rounded_size: self.rounded_size,
+            is_dir: self.is_dir,
+            inodes: self.inodes,
+            file_count: self.file_count,
+            modified: self.modified,
+            newest_modified: self.newest_modified,
+            mount_point: self.mount_point,
+            hardlink: self.hardlink,
+            symlink_loop: self.symlink_loop,
+            children: Vec::new(),
+        }
+    }
+
+    /// Entries below this one, in tree order, for which `pred` holds.
+    pub fn find_all(&self, pred: &impl Fn(&DirEntryInfo) -> bool) -> Vec<&DirEntryInfo> {
+        let mut found = Vec::new();
+        for child in &self.children {
+            if pred(child) {
+                found.push(child);
+            }
+            found.extend(child.find_all(pred));
+        }
+        found
+    }
+
+    /// The entry at `path` in this subtree, if the scan kept one.
+    pub fn find(&self, path: &Path) -> Option<&DirEntryInfo> {
+        if self.path == path {
+            return Some(self);
+        }
+        self.children
+            .iter()
+            .find(|c| path.starts_with(&c.path))
+            .and_then(|c| c.find(path))
+    }
+
+    /// Puts `child` in place of the child with the same path, adjusting this
+    /// entry's totals by the difference. Returns false if there's no such
+    /// child.
+    pub fn replace_child(&mut self, child: DirEntryInfo) -> bool {
+        let Some(pos) = self.children.iter().position(|c| c.path == child.path) else {
+            return false;
+        };
+        let old = std::mem::replace(&mut self.children[pos], child);
+        self.adjust(&old, &self.children[pos].without_children());
+        true
+    }
+
+    /// Puts `entry` in place of the entry with the same path anywhere in
+    /// this subtree, this one included, adjusting the totals of every entry
+    /// above it by the difference. Returns false if there's no such entry.
+    pub fn replace_descendant(&mut self, entry: DirEntryInfo) -> bool {
+        if self.path == entry.path {
+            *self = entry;
+            return true;
+        }
+        let Some(pos) = self
+            .children
+            .iter()
+            .position(|c| entry.path.starts_with(&c.path))
+        else {
+            return false;
+        };
+        let old = self.children[pos].without_children();
+        if !self.children[pos].replace_descendant(entry) {
+            return false;
+        }
+        self.adjust(&old, &self.children[pos].without_children());
+        true
+    }
+
+    /// Moves this entry's totals from counting `old` to counting `new`.
+    fn adjust(&mut self, old: &DirEntryInfo, new: &DirEntryInfo) {
+        self.size = self.size - old.size + new.size;
+        self.allocated_size = self.allocated_size - old.allocated_size + new.allocated_size;
+        self.rounded_size = self.rounded_size - old.rounded_size + new.rounded_size;
+        self.inodes = self.inodes - old.inodes + new.inodes;
+        self.file_count = self.file_count - old.file_count + new.file_count;
+        self.newest_modified = self.newest_modified.max(new.newest_modified);
+    }
+
+    /// Takes the child at `path` out of the tree, subtracting its totals.
+    pub fn remove_child(&mut self, path: &Path) -> Option<DirEntryInfo> {
+        let pos = self.children.iter().position(|c| c.path == path)?;
+        let child = self.children.remove(pos);
+        self.subtract(&child);
+        Some(child)
+    }
+
+    /// Takes the entry at `path` anywhere below this one out of the tree,
+    /// subtracting its totals from every entry above it.
+    pub fn remove_descendant(&mut self, path: &Path) -> Option<DirEntryInfo> {
+        let pos = self
+            .children
+            .iter()
+            .position(|c| path.starts_with(&c.path))?;
+        if self.children[pos].path == path {
+            return self.remove_child(path);
+        }
+        let removed = self.children[pos].remove_descendant(path)?;
+        self.subtract(&removed);
+        Some(removed)
+    }
+
+    fn subtract(&mut self, removed: &DirEntryInfo) {
+        self.size -= removed.size;
+        self.allocated_size -= removed.allocated_size;
+        self.rounded_size -= removed.rounded_size;
+        self.inodes -= removed.inodes;
+        self.file_count -= removed.file_count;
+    }
+
+    /// Mean size of the files in this subtree, or 0 if it holds none.
+    pub fn average_file_size(&self) -> u64 {
+        self.size.checked_div(self.file_count).unwrap_or(0)
+    }
+
+    /// Bytes in the files directly inside this directory, leaving out
+    /// subdirectories; a file's own size.
+    pub fn direct_size(&self) -> u64 {
+        if !self.is_dir {
+            return self.size;
+        }
+        self.children
+            .iter()
+            .filter(|c| !c.is_dir)
+            .map(|c| c.size)
+            .sum()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ScanOptions {
+    pub follow_symlinks: bool,
+    /// Follow symlinks only when their target stays inside the root; links
+    /// pointing elsewhere are kept as plain, unfollowed entries.
+    pub contain_symlinks: bool,
+    pub size_mode: SizeMode,
+    /// With `SizeMode::Apparent`, count the bytes sparse files actually
+    /// occupy instead of their length.
+    pub precise_sparse: bool,
+    /// Which kinds of entries add to the totals.
+    pub size_policy: SizePolicy,
+    /// When non-empty, only files matching one of these globs are kept.
+    /// Globs are tried against both the full path and the file name.
+    pub include: Vec<String>,
+    /// Entries matching one of these globs are skipped along with
+    /// everything below them. Matched like `include`.
+    pub exclude: Vec<String>,
+    /// Skip what `.gitignore`, `.ignore` and git's exclude files ignore.
+    /// Off by default, so ignored build output still counts.
+    pub use_gitignore: bool,
+    /// Don't descend into directories on another filesystem than the root;
+    /// they're kept as empty entries so the mount point still shows.
+    pub one_file_system: bool,
+    /// Stop walking once this much time has passed and build a partial tree.
+    pub timeout: Option<Duration>,
+    /// Stop walking after collecting this many entries.
+    pub max_entries: Option<usize>,
+    /// Round each file up to this many bytes for `DirEntryInfo::rounded_size`.
+    pub cluster_size: Option<u64>,
+    /// Keep nodes only this many levels below the root (0 is the root
+    /// alone); deeper entries are still walked and counted.
+    pub max_depth: Option<usize>,
+    /// Size of the pool reading metadata while one thread lists the
+    /// directories; one per core when unset.
+    pub threads: Option<usize>,
+    /// Add each file's extended attributes to its size. Costs a few extra
+    /// syscalls per file.
+    pub xattrs: bool,
+    /// Set from another thread (e.g. a Ctrl-C handler) to stop the walk and
+    /// build a partial tree from what was read so far.
+    pub cancel: Arc<AtomicBool>,
+}
+
+/// Which figure a file's size is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SizeMode {
+    /// Blocks allocated on disk, as `du` and `df` count them. Platforms
+    /// without block counts fall back to the length.
+    #[default]
+    Allocated,
+    /// The logical length, as `ls -l` shows it.
+    Apparent,
+}
+
+/// What counts toward a directory's size besides regular files. The default
+/// counts regular files only; `du` counts everything.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SizePolicy {
+    /// The links themselves (not their targets) when they aren't followed.
+    pub symlinks: bool,
+    /// Each directory's own blocks.
+    pub dir_overhead: bool,
+    /// Devices, sockets and FIFOs.
+    pub special: bool,
+}
+
+impl SizePolicy {
+    /// Everything `du` adds up.
+    pub const DU: SizePolicy = SizePolicy {
+        symlinks: true,
+        dir_overhead: true,
+        special: true,
+    };
+
+    /// Parses `du`, or a comma-separated list of `symlinks`, `dir-overhead`
+    /// and `special` to count on top of regular files; `regular` may appear
+    /// in the list and adds nothing.
+    pub fn parse(input: &str) -> Result<SizePolicy, String> {
+        if input.trim() == "du" {
+            return Ok(SizePolicy::DU);
+        }
+        let mut policy = SizePolicy::default();
+        for part in input.split(',').map(str::trim) {
+            match part {
+                "regular" => {}
+                "symlinks" => policy.symlinks = true,
+                "dir-overhead" => policy.dir_overhead = true,
+                "special" => policy.special = true,
+                _ => {
+                    return Err(format!(
+                        "unknown size policy `{part}` (use regular, du, or a list of symlinks, dir-overhead, special)"
+                    ))
+                }
+            }
+        }
+        Ok(policy)
+    }
+
+    /// Short description for the status bar, e.g. `files+symlinks`.
+    pub fn describe(self) -> String {
+        if self == SizePolicy::DU {
+            return "du".to_string();
+        }
+        let mut parts = vec!["files"];
+        if self.symlinks {
+            parts.push("symlinks");
+        }
+        if self.dir_overhead {
+            parts.push("dir overhead");
+        }
+        if self.special {
+            parts.push("special");
+        }
+        parts.join("+")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScanError {
+    pub path: PathBuf,
+    pub message: String,
+    pub kind: std::io::ErrorKind,
+}
+
+impl ScanError {
+    fn from_walk(err: &ignore::Error) -> Self {
+        fn error_path(err: &ignore::Error) -> Option<&Path> {
+            match err {
+                ignore::Error::WithPath { path, .. } => Some(path),
+                ignore::Error::WithDepth { err, .. }
+                | ignore::Error::WithLineNumber { err, .. } => error_path(err),
+                ignore::Error::Loop { child, .. } => Some(child),
+                _ => None,
+            }
+        }
+
+        let io_error = err.io_error();
+        ScanError {
+            path: error_path(err).map(Path::to_path_buf).unwrap_or_default(),
+            message: io_error.map_or_else(|| err.to_string(), |e| e.to_string()),
+            kind: io_error.map_or(std::io::ErrorKind::Other, |e| e.kind()),
+        }
+    }
+}
+
+/// Everything that can make the scanned totals smaller than what's really
+/// on disk.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Completeness {
+    /// The walk hit `ScanOptions::timeout`.
+    pub timed_out: bool,
+    /// The walk hit `ScanOptions::max_entries`.
+    pub truncated: bool,
+    /// The walk was stopped through `ScanOptions::cancel`.
+    pub cancelled: bool,
+    /// Entries that could not be read.
+    pub errors: usize,
+    /// Only files matching `ScanOptions::include` were counted.
+    pub filtered: bool,
+    /// Entries matching `ScanOptions::exclude` were skipped.
+    pub excluded: bool,
+    /// `.dua-ignore` files found; their rules may have excluded entries.
+    pub ignore_files: usize,
+    /// Git ignore rules were applied (`ScanOptions::use_gitignore`).
+    pub gitignored: bool,
+}
+
+impl Completeness {
+    /// Totals are exact when nothing was skipped.
+    pub fn is_exact(&self) -> bool {
+        self.reasons().is_empty()
+    }
+
+    /// The walk stopped before reading everything.
+    pub fn is_partial(&self) -> bool {
+        self.timed_out || self.truncated || self.cancelled
+    }
+
+    /// One line per reason the totals may fall short.
+    pub fn reasons(&self) -> Vec<String> {
+        let mut reasons = Vec::new();
+        if self.timed_out {
+            reasons.push("The scan stopped at --scan-timeout".to_string());
+        }
+        if self.truncated {
+            reasons.push("The scan stopped at --max-entries".to_string());
+        }
+        if self.cancelled {
+            reasons.push("The scan was cancelled with Ctrl-C".to_string());
+        }
+        if self.errors > 0 {
+            reasons.push(format!("{} entries couldn't be read", self.errors));
+        }
+        if self.filtered {
+            reasons.push("Only files matching --include are counted".to_string());
+        }
+        if self.excluded {
+            reasons.push("Entries matching --exclude are skipped".to_string());
+        }
+        if self.ignore_files > 0 {
+            reasons.push(format!(
+                "{} {} files may exclude entries",
+                self.ignore_files,
+                walk::IGNORE_FILENAME
+            ));
+        }
+        if self.gitignored {
+            reasons.push("Entries ignored by git (--use-gitignore) are skipped".to_string());
+        }
+        reasons
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScanResult {
+    pub tree: DirEntryInfo,
+    pub completeness: Completeness,
+    /// Entries that could not be read and are missing from the totals.
+    pub errors: Vec<ScanError>,
+    /// Wall time from the first to the last entry read under each of the
+    /// root's children, to point at slow subtrees.
+    pub scan_times: HashMap<PathBuf, Duration>,
+}
+
+/// Re-reads the size of every file already in `node`'s subtree and
+/// recomputes the directory totals, without looking for new or removed
+/// entries. Returns how many files could no longer be read; they count as
+/// empty until the next scan.
+pub fn restat(node: &mut DirEntryInfo, options: &ScanOptions) -> usize {
+    // Look at links the way the walk did
+    let md = if options.follow_symlinks || options.contain_symlinks {
+        std::fs::metadata(&node.path)
+    } else {
+        std::fs::symlink_metadata(&node.path)
+    }
+    .ok();
+    node.modified = md
+        .as_ref()
+        .and_then(|md| md.modified().ok())
+        .or(node.modified);
+    node.newest_modified = node.modified.max(node.newest_modified);
+
+    // Reading through it would size the directory it points back to
+    if node.symlink_loop {
+        return 0;
+    }
+    if !node.is_dir {
+        let Some(md) = md else {
+            node.size = 0;
+            node.allocated_size = 0;
+            node.rounded_size = 0;
+            return 1;
+        };
+        // Another name of this file carries its size
+        if node.hardlink.is_some_and(|link| !link.counted) {
+            return 0;
+        }
+        let sizes = walk::entry_sizes(&node.path, &md, options);
+        node.size = sizes.size;
+        node.allocated_size = sizes.allocated;
+        node.rounded_size = sizes.rounded;
+        return 0;
+    }
+
+    // A directory at the depth limit holds totals for entries it has no
+    // nodes for, which can't be re-read one by one
+    if options.max_depth.is_some() && node.children.is_empty() {
+        return 0;
+    }
+    let own = match &md {
+        Some(md) if options.size_policy.dir_overhead => size::dir_overhead(md),
+        _ => 0,
+    };
+    let mut missing = 0;
+    node.size = own;
+    node.allocated_size = own;
+    node.rounded_size = own;
+    for child in &mut node.children {
+        missing += restat(child, options);
+        node.newest_modified = node.newest_modified.max(child.newest_modified);
+        node.size += child.size;
+        node.allocated_size += child.allocated_size;
+        node.rounded_size += child.rounded_size;
+    }
+    missing
+}
+
+/// Files read between progress updates during the walk.
+const SCAN_PROGRESS_INTERVAL: u64 = 512;
+
+/// Files and bytes the walk has read, for the progress message.
+#[derive(Default)]
+struct ScanProgress {
+    files: u64,
+    bytes: u64,
+}
+
+impl ScanProgress {
+    /// Counts `entry`; true when it's time to update the message.
+    fn add(&mut self, entry: &WalkedEntry) -> bool {
+        if entry.is_dir {
+            return false;
+        }
+        self.files += 1;
+        self.bytes += entry.size;
+        self.files.is_multiple_of(SCAN_PROGRESS_INTERVAL)
+    }
+
+    fn message(&self, root: &Path) -> String {
+        format!(
+            "Scanning {}: {} files, {}",
+            root.display(),
+            units::format_count(self.files),
+            units::SizeFormat::default().format(self.bytes)
+        )
+    }
+}
+
+/// Room for entries the builder hasn't taken yet before the walk blocks.
+const CHANNEL_CAPACITY: usize = 4096;
+
+#[derive(Default)]
+struct Totals {
+    size: u64,
+    allocated: u64,
+    rounded: u64,
+    inodes: u64,
+    files: u64,
+    newest: Option<SystemTime>,
+}
+
+impl Totals {
+    fn add(&mut self, entry: &WalkedEntry) {
+        self.size += entry.size;
+        self.allocated += entry.allocated;
+        self.rounded += entry.rounded;
+        self.inodes += 1;
+        self.files += u64::from(!entry.is_dir);
+        self.newest = self.newest.max(entry.modified);
+    }
+}
+
+/// Assembles walked entries into a tree, adding each one to its ancestors'
+/// totals as it arrives.
+pub struct TreeBuilder {
+    root: PathBuf,
+    entries: Vec<WalkedEntry>,
+    sizes: HashMap<PathBuf, Totals>,
+    /// When the first and latest entries under each top-level child arrived.
+    arrivals: HashMap<PathBuf, (Instant, Instant)>,
+    max_depth: Option<usize>,
+    /// Hard-linked files, held back until every name is known.
+    linked: Vec<WalkedEntry>,
+}
+
+impl TreeBuilder {
+    /// With a `max_depth`, only entries that many levels below `root` get
+    /// nodes; deeper ones still count toward the totals.
+    pub fn new(root: &Path, max_depth: Option<usize>) -> Self {
+        TreeBuilder {
+            root: root.to_path_buf(),
+            max_depth,
+            linked: Vec::new(),
+            entries: Vec::new(),
+            sizes: HashMap::new(),
+            arrivals: HashMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, entry: WalkedEntry) {
+        if let Some(top) = entry
+            .path
+            .strip_prefix(&self.root)
+            .ok()
+            .and_then(|rest| rest.components().next())
+        {
+            let now = Instant::now();
+            self.arrivals
+                .entry(self.root.join(top))
+                .and_modify(|(_, last)| *last = now)
+                .or_insert((now, now));
+        }
+        // Which name of a hard-linked file counts is settled once all of
+        // them have arrived
+        if entry.hardlink.is_some() {
+            self.linked.push(entry);
+        } else {
+            self.add(entry);
+        }
+    }
+
+    fn add(&mut self, entry: WalkedEntry) {
+        // Entries past the depth limit only add to their ancestors at the
+        // limit and aren't kept themselves
+        let depth = entry
+            .path
+            .strip_prefix(&self.root)
+            .map_or(0, |rest| rest.components().count());
+        let skip = self.max_depth.map_or(0, |max| depth.saturating_sub(max));
+        for p in entry.path.ancestors().skip(skip) {
+            self.sizes.entry(p.to_path_buf()).or_default().add(&entry);
+        }
+        if skip == 0 {
+            self.entries.push(entry);
+        }
+    }
+
+    /// Counts each hard-linked file once, under its first name in path
+    /// order; its other names add nothing.
+    fn add_linked(&mut self) {
+        let mut linked = std::mem::take(&mut self.linked);
+        let identity = |e: &WalkedEntry| e.hardlink.map(|l| (l.device, l.inode));
+        linked.sort_by(|a, b| identity(a).cmp(&identity(b)).then(a.path.cmp(&b.path)));
+        let mut previous = None;
+        for mut entry in linked {
+            let id = identity(&entry);
+            if previous == Some(id) {
+                entry.size = 0;
+                entry.allocated = 0;
+                entry.rounded = 0;
+                if let Some(link) = &mut entry.hardlink {
+                    link.counted = false;
+                }
+            }
+            previous = Some(id);
+            self.add(entry);
+        }
+    }
+
+    /// How long each of the root's children took to scan so far.
+    pub fn scan_times(&self) -> HashMap<PathBuf, Duration> {
+        self.arrivals
+            .iter()
+            .map(|(path, (first, last))| (path.clone(), *last - *first))
+            .collect()
+    }
+
+    /// Assembles the tree, reporting progress on `pb` since this can take a
+    /// while for millions of entries.
+    pub fn finish(mut self, pb: &ProgressBar) -> DirEntryInfo {
+        self.add_linked();
+        pb.set_message(format!("Sorting {} entries", self.entries.len()));
+        self.assemble(pb)
+    }
+
+    /// The tree of the entries pushed so far, to show while the walk is
+    /// still going. Hard-linked files are left out until `finish` settles
+    /// which of their names counts.
+    pub fn snapshot(&mut self) -> DirEntryInfo {
+        self.assemble(&ProgressBar::hidden())
+    }
+
+    fn assemble(&mut self, pb: &ProgressBar) -> DirEntryInfo {
+        // Already sorted up to the entries pushed since, which sorts cheaply
+        self.entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+        // The root isn't one of the walked entries
+        let root_md = std::fs::metadata(&self.root).ok();
+        let root = WalkedEntry {
+            path: self.root.clone(),
+            size: 0,
+            allocated: 0,
+            rounded: 0,
+            is_dir: true,
+            modified: root_md.as_ref().and_then(|md| md.modified().ok()),
+            device: root_md.as_ref().and_then(volume::device_id),
+            hardlink: None,
+            symlink_loop: false,
+        };
+        // One pass to index every entry under its parent, so assembly
+        // doesn't search the whole list for each directory's children
+        let mut children: HashMap<&Path, Vec<usize>> = HashMap::new();
+        for (i, entry) in self.entries.iter().enumerate() {
+            if let Some(parent) = entry.path.parent() {
+                children.entry(parent).or_default().push(i);
+            }
+        }
+        // The walk reports the root too, first in path order
+        let walked_root = self.entries.first().is_some_and(|e| e.path == self.root);
+        let mut assembly = Assembly {
+            sizes: &self.sizes,
+            entries: &self.entries,
+            children: &children,
+            pb,
+            built: 0,
+            total: self.entries.len() - usize::from(walked_root),
+        };
+        let tree = assembly.node(&root, root.device);
+        assembly.report();
+        tree
+    }
+}
+
+/// Nodes built between progress updates, to keep the bar off the hot path.
+const PROGRESS_INTERVAL: usize = 4096;
+
+struct Assembly<'a> {
+    sizes: &'a HashMap<PathBuf, Totals>,
+    entries: &'a [WalkedEntry],
+    /// Indexes into `entries` of each directory's children, in path order.
+    children: &'a HashMap<&'a Path, Vec<usize>>,
+    pb: &'a ProgressBar,
+    /// Entries below the root built so far, out of `total`.
+    built: usize,
+    total: usize,
+}
+
+impl Assembly<'_> {
+    fn node(&mut self, entry: &WalkedEntry, parent_device: Option<u64>) -> DirEntryInfo {
+        let path = entry.path.as_path();
+        // Files don't record a device; they share their directory's
+        let device = entry.device.or(parent_device);
+        let mount_point = match (device, parent_device) {
+            (Some(device), Some(parent)) => device != parent,
+            _ => false,
+        };
+
+        let (entries, index) = (self.entries, self.children);
+        let children: Vec<DirEntryInfo> = index
+            .get(path)
+            .map_or(&[][..], Vec::as_slice)
+            .iter()
+            .map(|&i| self.node(&entries[i], device))
+            .collect();
+
+        // Counted as their parent takes them, so the root isn't
+        let before = self.built / PROGRESS_INTERVAL;
+        self.built += children.len();
+        if self.built / PROGRESS_INTERVAL != before {
+            self.report();
+        }
+
+        let totals = self.sizes.get(path);
+        DirEntryInfo {
+            path: path.to_path_buf(),
+            size: totals.map_or(0, |t| t.size),
+            allocated_size: totals.map_or(0, |t| t.allocated),
+            rounded_size: totals.map_or(0, |t| t.rounded),
+            is_dir: entry.is_dir,
+            inodes: totals.map_or(0, |t| t.inodes),
+            file_count: totals.map_or(0, |t| t.files),
+            modified: entry.modified,
+            // Also covers entries past the depth limit, which have no nodes
+            newest_modified: totals.and_then(|t| t.newest).max(entry.modified),
+            mount_point,
+            hardlink: entry.hardlink,
+            symlink_loop: entry.symlink_loop,
+            children,
+        }
+    }
+
+    fn report(&self) {
+        self.pb.set_message(format!(
+            "Building tree: {}/{} entries",
+            self.built, self.total
+        ));
+    }
+}
+
+/// Scans `root` and builds the whole tree, feeding the walk into a
+/// `TreeBuilder` as it goes. `pb`'s message follows the build phases.
 pub fn build_tree(
     root: &Path,
-    follow_symlinks: bool,
-    _pb: &ProgressBar,
-) -> std::io::Result<DirEntryInfo> {
-    let mut entries: Vec<(PathBuf, u64, bool)> = WalkBuilder::new(root)
-        .follow_links(follow_symlinks)
-        .hidden(false)
-        .threads(num_cpus::get())
-        .build()
-        .par_bridge()
-        .filter_map(|entry| match entry {
-            Ok(dirent) => {
-                if dirent.file_type().map(|ft| ft.is_file()).unwrap_or(false) {
-                    let md = dirent.metadata().ok()?;
-                    let sz = md.len();
-                    Some((dirent.into_path(), sz, false))
-                } else {
-                    Some((dirent.into_path(), 0, true))
-                }
+    options: &ScanOptions,
+    pb: &ProgressBar,
+) -> std::io::Result<ScanResult> {
+    let (tx, rx) = sync_channel(CHANNEL_CAPACITY);
+    std::thread::scope(|scope| {
+        let walker = scope.spawn(|| walk::walk(root, options, tx));
+        let mut builder = TreeBuilder::new(root, options.max_depth);
+        let mut progress = ScanProgress::default();
+        for entry in rx {
+            if progress.add(&entry) {
+                pb.set_message(progress.message(root));
             }
-            Err(_) => None,
-        })
-        .collect();
-
-    entries.sort_by_key(|(p, _, _)| p.clone());
-
-    use std::collections::HashMap;
-    let mut sizes: HashMap<PathBuf, u64> = HashMap::new();
-    for (path, size, _) in &entries {
-        sizes.entry(path.clone()).or_default();
-        if *size > 0 {
-            sizes.entry(path.clone()).and_modify(|s| *s += *size);
-        }
-        let mut cur = path.parent();
-        while let Some(p) = cur {
-            sizes.entry(p.to_path_buf()).or_default();
-            sizes.entry(p.to_path_buf()).and_modify(|s| *s += *size);
-            cur = p.parent();
-        }
-    }
-
-    fn build_node(
-        path: &Path,
-        sizes: &HashMap<PathBuf, u64>,
-        is_dir: bool,
-        entries: &[(PathBuf, u64, bool)],
-    ) -> DirEntryInfo {
-        let children_paths: Vec<&(PathBuf, u64, bool)> = entries
+            builder.push(entry);
+        }
+        let outcome = walker.join().expect("scan thread panicked")?;
+        Ok(scan_result(builder, outcome, options, pb))
+    })
+}
+
+/// Finishes the tree from a completed walk and records how complete it is.
+pub fn scan_result(
+    builder: TreeBuilder,
+    outcome: WalkOutcome,
+    options: &ScanOptions,
+    pb: &ProgressBar,
+) -> ScanResult {
+    let scan_times = builder.scan_times();
+    ScanResult {
+        tree: builder.finish(pb),
+        completeness: Completeness {
+            timed_out: outcome.timed_out,
+            truncated: outcome.truncated,
+            cancelled: outcome.cancelled,
+            errors: outcome.errors.len(),
+            filtered: !options.include.is_empty(),
+            excluded: !options.exclude.is_empty(),
+            ignore_files: outcome.ignore_files,
+            gitignored: options.use_gitignore,
+        },
+        errors: outcome.errors,
+        scan_times,
+    }
+}
+
+/// Puts the trees of several scans under one parent at `path`, which holds
+/// them all, so separate roots can be browsed side by side. The parent's
+/// totals are the sum of the roots'; nothing else under it is counted.
+pub fn combine_scans(path: &Path, scans: Vec<ScanResult>) -> ScanResult {
+    let modified = std::fs::metadata(path).and_then(|md| md.modified()).ok();
+    let mut tree = DirEntryInfo {
+        path: path.to_path_buf(),
+        size: 0,
+        allocated_size: 0,
+        rounded_size: 0,
+        is_dir: true,
+        inodes: 1,
+        file_count: 0,
+        modified,
+        newest_modified: modified,
+        mount_point: false,
+        hardlink: None,
+        symlink_loop: false,
+        children: Vec::new(),
+    };
+    let mut completeness = Completeness::default();
+    let mut errors = Vec::new();
+    let mut scan_times = HashMap::new();
+    for scan in scans {
+        tree.size += scan.tree.size;
+        tree.allocated_size += scan.tree.allocated_size;
+        tree.rounded_size += scan.tree.rounded_size;
+        tree.inodes += scan.tree.inodes;
+        tree.file_count += scan.tree.file_count;
+        tree.newest_modified = tree.newest_modified.max(scan.tree.newest_modified);
+        tree.children.push(scan.tree);
+
+        let part = scan.completeness;
+        completeness.timed_out |= part.timed_out;
+        completeness.truncated |= part.truncated;
+        completeness.cancelled |= part.cancelled;
+        completeness.errors += part.errors;
+        completeness.filtered |= part.filtered;
+        completeness.excluded |= part.excluded;
+        completeness.ignore_files += part.ignore_files;
+        completeness.gitignored |= part.gitignored;
+        errors.extend(scan.errors);
+        scan_times.extend(scan.scan_times);
+    }
+    ScanResult {
+        tree,
+        completeness,
+        errors,
+        scan_times,
+    }
+}
+
+/// What `stream_tree` sends while it runs.
+pub enum ScanUpdate {
+    /// Entries read since the last update, in no particular order.
+    Entries(Vec<WalkedEntry>),
+    /// The walk is over; nothing is sent after this.
+    Done(std::io::Result<WalkOutcome>),
+}
+
+/// Most entries sent in one `ScanUpdate::Entries`.
+const STREAM_BATCH: usize = 4096;
+
+/// Walks `root` like `build_tree` but passes the entries on to `tx` in
+/// batches instead of building the tree, so the receiver can show it while
+/// it grows; feeding them to a `TreeBuilder` and handing the outcome to
+/// `scan_result` gives the same tree `build_tree` would. Sending never
+/// blocks the walk. If the receiver goes away the walk still runs to the
+/// end, unless `options.cancel` is set.
+pub fn stream_tree(root: &Path, options: &ScanOptions, tx: Sender<ScanUpdate>) {
+    let (entries_tx, entries_rx) = sync_channel(CHANNEL_CAPACITY);
+    std::thread::scope(|scope| {
+        let walker = scope.spawn(|| walk::walk(root, options, entries_tx));
+        // Wait for one entry, then take whatever else is already queued
+        while let Ok(first) = entries_rx.recv() {
+            let mut batch = vec![first];
+            batch.extend(entries_rx.try_iter().take(STREAM_BATCH - 1));
+            if tx.send(ScanUpdate::Entries(batch)).is_err() {
+                break;
+            }
+        }
+        // Drop the receiver so the walk doesn't block if we stopped early
+        drop(entries_rx);
+        let outcome = walker.join().expect("scan thread panicked");
+        let _ = tx.send(ScanUpdate::Done(outcome));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    fn scan(root: &Path, options: &ScanOptions) -> ScanResult {
+        build_tree(root, options, &ProgressBar::hidden()).unwrap()
+    }
+
+    /// Apparent sizes, so totals are the bytes written.
+    fn apparent() -> ScanOptions {
+        ScanOptions {
+            size_mode: SizeMode::Apparent,
+            ..ScanOptions::default()
+        }
+    }
+
+    fn entry(path: &str, size: u64, is_dir: bool, device: Option<u64>) -> WalkedEntry {
+        WalkedEntry {
+            path: PathBuf::from(path),
+            size,
+            allocated: size,
+            rounded: size,
+            is_dir,
+            modified: None,
+            device,
+            hardlink: None,
+            symlink_loop: false,
+        }
+    }
+
+    fn size_of(tree: &DirEntryInfo, path: &Path) -> u64 {
+        tree.find(path)
+            .unwrap_or_else(|| panic!("{} missing", path.display()))
+            .size
+    }
+
+    #[test]
+    fn parses_size_policies() {
+        assert_eq!(SizePolicy::parse("regular"), Ok(SizePolicy::default()));
+        assert_eq!(SizePolicy::parse("du"), Ok(SizePolicy::DU));
+        let links = SizePolicy {
+            symlinks: true,
+            ..SizePolicy::default()
+        };
+        assert_eq!(SizePolicy::parse("regular,symlinks"), Ok(links));
+        assert_eq!(SizePolicy::parse("symlinks, regular"), Ok(links));
+        assert_eq!(
+            SizePolicy::parse("symlinks,dir-overhead,special"),
+            Ok(SizePolicy::DU)
+        );
+        assert!(SizePolicy::parse("regular,du").is_err());
+        assert!(SizePolicy::parse("links").is_err());
+    }
+
+    #[test]
+    fn include_and_exclude_together() {
+        let dir = TempDir::new("include-exclude");
+        dir.file("a.mp4", 100);
+        dir.file("a.txt", 10);
+        dir.file("clips/b.mp4", 200);
+        dir.file("old/c.mp4", 400);
+        let options = ScanOptions {
+            include: vec!["*.mp4".to_string()],
+            exclude: vec!["old".to_string()],
+            ..apparent()
+        };
+
+        // Exclusion wins: nothing under `old` counts even though it matches
+        let result = scan(dir.path(), &options);
+        assert_eq!(result.tree.size, 300);
+        assert!(result.tree.find(&dir.path().join("a.txt")).is_none());
+        assert!(result.tree.find(&dir.path().join("old")).is_none());
+        assert_eq!(size_of(&result.tree, &dir.path().join("clips")), 200);
+        assert!(result.completeness.filtered && result.completeness.excluded);
+    }
+
+    #[test]
+    fn max_depth_keeps_totals_below_the_limit() {
+        let dir = TempDir::new("max-depth");
+        dir.file("a/b/c/d", 100);
+        dir.file("a/b/e", 10);
+        dir.file("a/f", 1);
+        let options = ScanOptions {
+            max_depth: Some(2),
+            ..apparent()
+        };
+
+        let tree = scan(dir.path(), &options).tree;
+        let leaf = tree.find(&dir.path().join("a/b")).unwrap();
+        assert!(leaf.children.is_empty());
+        assert_eq!(leaf.size, 110);
+        assert_eq!(leaf.file_count, 2);
+        assert_eq!(size_of(&tree, &dir.path().join("a")), 111);
+        assert_eq!(tree.size, 111);
+
+        let root_only = ScanOptions {
+            max_depth: Some(0),
+            ..apparent()
+        };
+        let tree = scan(dir.path(), &root_only).tree;
+        assert!(tree.children.is_empty());
+        assert_eq!(tree.size, 111);
+    }
+
+    #[test]
+    fn excluded_directories_add_nothing() {
+        let dir = TempDir::new("exclude");
+        dir.file("src/main.rs", 10);
+        dir.file("node_modules/pkg/index.js", 1000);
+        dir.file("src/node_modules/dep.js", 1000);
+        dir.file("target/debug/bin", 5000);
+        let options = ScanOptions {
+            exclude: vec!["node_modules".to_string(), "**/target".to_string()],
+            ..apparent()
+        };
+
+        let tree = scan(dir.path(), &options).tree;
+        assert_eq!(tree.size, 10);
+        assert_eq!(size_of(&tree, &dir.path().join("src")), 10);
+        assert!(tree.find(&dir.path().join("src/node_modules")).is_none());
+        assert!(tree.find(&dir.path().join("target")).is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hard_links_count_once() {
+        let dir = TempDir::new("hard-links");
+        let original = dir.file("a/file", 4096);
+        std::fs::create_dir(dir.path().join("b")).unwrap();
+        std::fs::hard_link(&original, dir.path().join("b/link")).unwrap();
+
+        let tree = scan(dir.path(), &apparent()).tree;
+        assert_eq!(tree.size, 4096);
+        // The first name in path order carries the size
+        assert_eq!(size_of(&tree, &dir.path().join("a")), 4096);
+        assert_eq!(size_of(&tree, &dir.path().join("b")), 0);
+        let link = tree.find(&dir.path().join("b/link")).unwrap();
+        assert!(link.hardlink.is_some_and(|link| !link.counted));
+    }
+
+    #[test]
+    fn directories_on_another_device_are_empty_mount_points() {
+        let dir = TempDir::new("mount-points");
+        let Some(device) = volume::device_id(&std::fs::metadata(dir.path()).unwrap()) else {
+            return;
+        };
+        let root = dir.path().to_str().unwrap();
+        let mut builder = TreeBuilder::new(dir.path(), None);
+        builder.push(entry(&format!("{root}/home"), 0, true, Some(device)));
+        builder.push(entry(&format!("{root}/home/f"), 10, false, None));
+        // What the walk reports for a pruned mount: the directory alone
+        builder.push(entry(&format!("{root}/mnt"), 0, true, Some(device + 1)));
+
+        let tree = builder.finish(&ProgressBar::hidden());
+        let mnt = tree.find(&dir.path().join("mnt")).unwrap();
+        assert!(mnt.mount_point && mnt.children.is_empty());
+        assert_eq!(mnt.size, 0);
+        assert!(!tree.find(&dir.path().join("home")).unwrap().mount_point);
+        assert_eq!(tree.size, 10);
+    }
+
+    /// The tree the old assembly built, searching the entries for each
+    /// node's children and descendants. Each level only searches what is
+    /// below it, to keep the test quick.
+    fn assemble_naively(path: &Path, is_dir: bool, entries: &[WalkedEntry]) -> DirEntryInfo {
+        let below: Vec<WalkedEntry> = entries
             .iter()
-            .filter(|(p, _, _)| p.parent() == Some(path))
+            .filter(|e| e.path.starts_with(path))
+            .cloned()
             .collect();
-        let children = children_paths
+        let mut children: Vec<&WalkedEntry> = below
             .iter()
-            .map(|(p, _, isd)| build_node(p, sizes, *isd, entries))
+            .filter(|e| e.path.parent() == Some(path))
             .collect();
+        children.sort_by(|a, b| a.path.cmp(&b.path));
+        let size = below.iter().map(|e| e.size).sum();
         DirEntryInfo {
             path: path.to_path_buf(),
-            size: *sizes.get(path).unwrap_or(&0),
+            size,
+            allocated_size: size,
+            rounded_size: size,
             is_dir,
-            children,
+            inodes: below.len() as u64,
+            file_count: below.iter().filter(|e| !e.is_dir).count() as u64,
+            modified: None,
+            newest_modified: None,
+            mount_point: false,
+            hardlink: None,
+            symlink_loop: false,
+            children: children
+                .into_iter()
+                .map(|e| assemble_naively(&e.path, e.is_dir, &below))
+                .collect(),
         }
     }
 
-    let root_node = build_node(root, &sizes, true, &entries);
-    Ok(root_node)
+    #[test]
+    fn assembles_ten_thousand_entries_like_the_naive_build() {
+        let mut entries = Vec::new();
+        for d in 0..100 {
+            entries.push(entry(&format!("/synthetic/d{d}"), 0, true, None));
+            for f in 0..99 {
+                entries.push(entry(
+                    &format!("/synthetic/d{d}/f{f}"),
+                    d * 100 + f,
+                    false,
+                    None,
+                ));
+            }
+        }
+        let root = Path::new("/synthetic");
+        let mut builder = TreeBuilder::new(root, None);
+        // Entries arrive in no particular order
+        for e in entries.iter().rev() {
+            builder.push(e.clone());
+        }
+
+        let tree = builder.finish(&ProgressBar::hidden());
+        let expected = assemble_naively(root, true, &entries);
+        assert_eq!(tree.inodes, 10_000);
+        assert_eq!(
+            serde_json::to_value(&tree).unwrap(),
+            serde_json::to_value(&expected).unwrap()
+        );
+    }
+
+    #[test]
+    fn progress_counts_every_file() {
+        let dir = TempDir::new("progress");
+        for i in 0..1100 {
+            dir.file(&format!("d{}/f{}", i % 3, i), 2);
+        }
+        let (tx, rx) = std::sync::mpsc::sync_channel(CHANNEL_CAPACITY);
+        let mut progress = ScanProgress::default();
+        let updates = std::thread::scope(|scope| {
+            let options = apparent();
+            scope.spawn(move || walk::walk(dir.path(), &options, tx).unwrap());
+            rx.iter().filter(|entry| progress.add(entry)).count()
+        });
+
+        assert_eq!(progress.files, 1100);
+        assert_eq!(progress.bytes, 2200);
+        assert_eq!(updates, 2);
+        assert!(progress.message(Path::new("/r")).contains("1,100 files"));
+    }
+
+    #[test]
+    fn file_counts_add_up_the_tree() {
+        let dir = TempDir::new("file-counts");
+        for name in ["a/1", "a/2", "a/b/3", "a/b/c/4", "a/b/c/5", "d/6", "7"] {
+            dir.file(name, 1);
+        }
+        std::fs::create_dir(dir.path().join("empty")).unwrap();
+
+        let tree = scan(dir.path(), &apparent()).tree;
+        let count = |path: &str| tree.find(&dir.path().join(path)).unwrap().file_count;
+        assert_eq!(tree.file_count, 7);
+        assert_eq!(count("a"), 5);
+        assert_eq!(count("a/b"), 3);
+        assert_eq!(count("a/b/c"), 2);
+        assert_eq!(count("d"), 1);
+        assert_eq!(count("7"), 1);
+        assert_eq!(count("empty"), 0);
+        // Directories count as entries but not as files
+        assert_eq!(tree.find(&dir.path().join("a")).unwrap().inodes, 8);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn an_unreadable_directory_is_one_error() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new("unreadable");
+        dir.file("open/f", 10);
+        dir.file("locked/g", 10);
+        let locked = dir.path().join("locked");
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+        // Root reads it anyway
+        let readable = std::fs::read_dir(&locked).is_ok();
+        let result = scan(dir.path(), &apparent());
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+        if readable {
+            return;
+        }
+
+        assert_eq!(result.errors.len(), 1);
+        assert_eq!(result.errors[0].path, locked);
+        assert_eq!(result.errors[0].kind, std::io::ErrorKind::PermissionDenied);
+        assert_eq!(result.completeness.errors, 1);
+        assert_eq!(result.tree.size, 10);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_dangling_followed_link_is_one_error() {
+        let dir = TempDir::new("dangling-link");
+        dir.file("f", 10);
+        let link = dir.path().join("gone");
+        std::os::unix::fs::symlink(dir.path().join("missing"), &link).unwrap();
+        let options = ScanOptions {
+            follow_symlinks: true,
+            ..apparent()
+        };
+
+        let result = scan(dir.path(), &options);
+        assert_eq!(result.errors.len(), 1);
+        assert_eq!(result.errors[0].path, link);
+        assert_eq!(result.errors[0].kind, std::io::ErrorKind::NotFound);
+        assert!(!result.completeness.is_exact());
+        assert_eq!(result.tree.size, 10);
+    }
+
+    #[test]
+    fn gitignored_files_only_drop_out_when_asked() {
+        let dir = TempDir::new("gitignore");
+        std::fs::create_dir(dir.path().join(".git")).unwrap();
+        std::fs::write(dir.path().join(".gitignore"), "*.bin\n").unwrap();
+        dir.file("build/huge.bin", 100_000);
+        dir.file("src/lib.rs", 100);
+        let gitignore_len = 6;
+
+        let everything = scan(dir.path(), &apparent());
+        assert_eq!(everything.tree.size, 100_100 + gitignore_len);
+        assert!(!everything.completeness.gitignored);
+
+        let options = ScanOptions {
+            use_gitignore: true,
+            ..apparent()
+        };
+        let trimmed = scan(dir.path(), &options);
+        assert_eq!(trimmed.tree.size, 100 + gitignore_len);
+        assert!(trimmed
+            .tree
+            .find(&dir.path().join("build/huge.bin"))
+            .is_none());
+        assert!(trimmed.completeness.gitignored);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_link_back_to_an_ancestor_is_an_empty_loop() {
+        let dir = TempDir::new("symlink-loop");
+        dir.file("a/f", 10);
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("a/up")).unwrap();
+        std::os::unix::fs::symlink(".", dir.path().join("self")).unwrap();
+        let options = ScanOptions {
+            follow_symlinks: true,
+            ..apparent()
+        };
+
+        let result = scan(dir.path(), &options);
+        for name in ["a/up", "self"] {
+            let link = result.tree.find(&dir.path().join(name)).unwrap();
+            assert!(link.symlink_loop && link.children.is_empty(), "{}", name);
+            assert_eq!(link.size, 0);
+        }
+        assert_eq!(result.tree.size, 10);
+        assert!(result.errors.is_empty());
+    }
+
+    #[test]
+    fn a_streamed_scan_builds_the_batch_tree() {
+        let dir = TempDir::new("stream");
+        for i in 0..300 {
+            dir.file(&format!("d{}/e{}/f{}", i % 4, i % 7, i), i);
+        }
+        let options = apparent();
+        let pb = ProgressBar::hidden();
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        stream_tree(dir.path(), &options, tx);
+        let mut builder = TreeBuilder::new(dir.path(), options.max_depth);
+        let mut outcome = None;
+        for update in rx {
+            match update {
+                ScanUpdate::Entries(entries) => entries.into_iter().for_each(|e| builder.push(e)),
+                ScanUpdate::Done(done) => outcome = Some(done.unwrap()),
+            }
+        }
+        let streamed = scan_result(builder, outcome.unwrap(), &options, &pb);
+
+        let batch = build_tree(dir.path(), &options, &pb).unwrap();
+        assert_eq!(
+            serde_json::to_value(&streamed.tree).unwrap(),
+            serde_json::to_value(&batch.tree).unwrap()
+        );
+        assert!(streamed.completeness.is_exact());
+    }
+
+    #[test]
+    fn combining_scans_adds_up_the_roots() {
+        let dir = TempDir::new("combine-scans");
+        dir.file("a/one", 100);
+        dir.file("a/two", 20);
+        dir.file("b/three", 3);
+        let a = scan(&dir.path().join("a"), &apparent());
+        let mut b = scan(&dir.path().join("b"), &apparent());
+        b.completeness.truncated = true;
+        b.completeness.errors = 2;
+
+        let combined = combine_scans(dir.path(), vec![a, b]);
+        let tree = &combined.tree;
+        assert_eq!(tree.path, dir.path());
+        assert_eq!(tree.children.len(), 2);
+        assert_eq!(tree.size, 123);
+        assert_eq!(tree.file_count, 3);
+        // The synthetic parent plus both scanned trees
+        assert_eq!(tree.inodes, 1 + 3 + 2);
+        assert!(combined.completeness.truncated);
+        assert!(!combined.completeness.cancelled);
+        assert_eq!(combined.completeness.errors, 2);
+    }
+
+    #[test]
+    fn replacing_a_subtree_updates_its_ancestors() {
+        let dir = TempDir::new("replace-subtree");
+        dir.file("a/b/grows", 10);
+        dir.file("a/stays", 5);
+        dir.file("other", 1);
+        let mut tree = scan(dir.path(), &apparent()).tree;
+        assert_eq!(tree.size, 16);
+
+        dir.file("a/b/grows", 1000);
+        dir.file("a/b/new", 7);
+        let rescanned = scan(&dir.path().join("a/b"), &apparent()).tree;
+        assert!(tree.replace_descendant(rescanned));
+
+        assert_eq!(size_of(&tree, &dir.path().join("a/b")), 1007);
+        assert_eq!(size_of(&tree, &dir.path().join("a")), 1012);
+        assert_eq!(tree.size, 1013);
+        assert_eq!(tree.file_count, 4);
+    }
+
+    #[test]
+    fn removing_a_descendant_updates_its_ancestors() {
+        let dir = TempDir::new("remove-descendant");
+        dir.file("a/b/gone", 10);
+        dir.file("a/stays", 5);
+        let mut tree = scan(dir.path(), &apparent()).tree;
+
+        let gone = dir.path().join("a/b/gone");
+        assert_eq!(tree.remove_descendant(&gone).map(|e| e.size), Some(10));
+        assert!(tree.find(&gone).is_none());
+        assert_eq!(size_of(&tree, &dir.path().join("a/b")), 0);
+        assert_eq!(size_of(&tree, &dir.path().join("a")), 5);
+        assert_eq!(tree.size, 5);
+        assert_eq!(tree.file_count, 1);
+        assert!(tree.remove_descendant(&gone).is_none());
+    }
+
+    #[test]
+    fn building_progress_ends_at_every_entry() {
+        let dir = TempDir::new("build-progress");
+        dir.file("a/b", 1);
+        dir.file("c", 1);
+        let pb = ProgressBar::hidden();
+        build_tree(dir.path(), &ScanOptions::default(), &pb).unwrap();
+        assert_eq!(pb.message(), "Building tree: 3/3 entries");
+    }
 }
@@ -0,0 +1,109 @@
+/// How much `--nice` raises the nice value by, as `nice` does by default.
+#[cfg(unix)]
+const NICE_INCREMENT: i32 = 10;
+
+/// The highest (least favoured) nice value.
+#[cfg(unix)]
+const MAX_NICE: i32 = 19;
+
+/// Lowers the CPU priority and, on Linux, moves disk I/O to the idle class.
+/// Both are inherited by threads started afterwards, so this has to run
+/// before the walk begins. A no-op where unsupported.
+#[cfg(unix)]
+pub fn lower_priority() -> std::io::Result<()> {
+    // setpriority takes the new value, not a step, and an unprivileged
+    // process can't go back down, so start from what's there
+    let target = raised_nice(current_nice()?);
+    // SAFETY: plain syscall on the calling process, no pointers involved.
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, target) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    #[cfg(target_os = "linux")]
+    {
+        const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+        const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+        const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+        // SAFETY: ioprio_set takes only integer arguments.
+        let rc = unsafe {
+            libc::syscall(
+                libc::SYS_ioprio_set,
+                IOPRIO_WHO_PROCESS,
+                0,
+                IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+            )
+        };
+        if rc != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn lower_priority() -> std::io::Result<()> {
+    Ok(())
+}
+
+/// `current` raised by `--nice`, capped at the top.
+#[cfg(unix)]
+fn raised_nice(current: i32) -> i32 {
+    (current + NICE_INCREMENT).min(MAX_NICE)
+}
+
+/// The process's nice value. getpriority can return -1 as a value, so
+/// errors show only through errno, cleared beforehand.
+#[cfg(unix)]
+fn current_nice() -> std::io::Result<i32> {
+    // SAFETY: errno is thread-local, and getpriority takes no pointers.
+    let nice = unsafe {
+        *errno() = 0;
+        libc::getpriority(libc::PRIO_PROCESS, 0)
+    };
+    if nice == -1 {
+        let err = std::io::Error::last_os_error();
+        if err.raw_os_error() != Some(0) {
+            return Err(err);
+        }
+    }
+    Ok(nice)
+}
+
+#[cfg(unix)]
+unsafe fn errno() -> *mut libc::c_int {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    return libc::__errno_location();
+    #[cfg(any(
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "dragonfly"
+    ))]
+    return libc::__error();
+    #[cfg(any(target_os = "netbsd", target_os = "openbsd"))]
+    return libc::__errno();
+}
+
+/// Metadata reader threads to use under `--nice`: half the cores, at least one.
+pub fn nice_threads() -> usize {
+    (num_cpus::get() / 2).max(1)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_nice_value_goes_up_by_the_increment_and_stops_at_the_top() {
+        assert_eq!(raised_nice(0), 10);
+        assert_eq!(raised_nice(-5), 5);
+        assert_eq!(raised_nice(5), 15);
+        assert_eq!(raised_nice(15), MAX_NICE);
+        assert_eq!(raised_nice(MAX_NICE), MAX_NICE);
+    }
+
+    #[test]
+    fn reads_the_current_nice_value() {
+        let nice = current_nice().unwrap();
+        assert!((-20..=MAX_NICE).contains(&nice));
+    }
+}
@@ -0,0 +1,155 @@
+use std::fs::Metadata;
+use std::path::Path;
+
+/// Bytes a file really occupies, for sparse images where `len()` overstates
+/// usage. Uses the block count when the filesystem reports one and otherwise
+/// probes the data extents with `SEEK_DATA`/`SEEK_HOLE`. Falls back to the
+/// logical length when neither is available.
+pub fn precise_allocated_size(path: &Path, md: &Metadata) -> u64 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if md.blocks() > 0 || md.len() == 0 {
+            return md.blocks() * 512;
+        }
+    }
+    probe_data_extents(path, md.len()).unwrap_or(md.len())
+}
+
+/// Bytes allocated on disk according to the block count. Platforms without
+/// block counts report the logical length.
+pub fn allocated_size(md: &Metadata) -> u64 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        md.blocks() * 512
+    }
+    #[cfg(not(unix))]
+    {
+        md.len()
+    }
+}
+
+/// Space taken by a directory's own entry table. Only Unix reports it; other
+/// platforms count directories as free.
+pub fn dir_overhead(md: &Metadata) -> u64 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        md.blocks() * 512
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = md;
+        0
+    }
+}
+
+/// Total bytes of the extended attributes on `path` (including the resource
+/// fork on macOS), which `len()` leaves out. Zero when the platform or
+/// filesystem doesn't support them.
+pub fn xattr_size(path: &Path) -> u64 {
+    xattr_values(path).unwrap_or(0)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+fn xattr_values(path: &Path) -> Option<u64> {
+    use std::ffi::{CStr, CString};
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    // Ask for the size of the name list first, then read it. SAFETY: the
+    // buffers passed are either null with size 0 or `names` with its length.
+    let len = unsafe { list_names(&path, std::ptr::null_mut(), 0) };
+    if len <= 0 {
+        return None;
+    }
+    let mut names = vec![0u8; len as usize];
+    let len = unsafe { list_names(&path, names.as_mut_ptr().cast(), names.len()) };
+    if len <= 0 {
+        return None;
+    }
+    names.truncate(len as usize);
+    let total = names
+        .split_inclusive(|&b| b == 0)
+        .filter_map(|name| CStr::from_bytes_with_nul(name).ok())
+        .map(|name| unsafe { value_len(&path, name) }.max(0) as u64)
+        .sum();
+    Some(total)
+}
+
+/// `llistxattr`, which doesn't follow a symlink at `path`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+unsafe fn list_names(path: &std::ffi::CStr, buf: *mut libc::c_char, size: usize) -> isize {
+    libc::llistxattr(path.as_ptr(), buf, size)
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn list_names(path: &std::ffi::CStr, buf: *mut libc::c_char, size: usize) -> isize {
+    libc::listxattr(path.as_ptr(), buf, size, libc::XATTR_NOFOLLOW)
+}
+
+/// Length of the attribute `name`, read without copying its value.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+unsafe fn value_len(path: &std::ffi::CStr, name: &std::ffi::CStr) -> isize {
+    libc::lgetxattr(path.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0)
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn value_len(path: &std::ffi::CStr, name: &std::ffi::CStr) -> isize {
+    libc::getxattr(
+        path.as_ptr(),
+        name.as_ptr(),
+        std::ptr::null_mut(),
+        0,
+        0,
+        libc::XATTR_NOFOLLOW,
+    )
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+fn xattr_values(_path: &Path) -> Option<u64> {
+    None
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "freebsd"
+))]
+fn probe_data_extents(path: &Path, len: u64) -> Option<u64> {
+    use std::os::unix::io::AsRawFd;
+
+    let file = std::fs::File::open(path).ok()?;
+    let fd = file.as_raw_fd();
+    let mut allocated = 0;
+    let mut pos: libc::off_t = 0;
+    while (pos as u64) < len {
+        // SAFETY: `fd` stays open for the lifetime of `file`.
+        let data = unsafe { libc::lseek(fd, pos, libc::SEEK_DATA) };
+        if data < 0 {
+            // ENXIO means there is no data past `pos`; anything else means
+            // the filesystem can't answer.
+            let no_more_data = std::io::Error::last_os_error().raw_os_error() == Some(libc::ENXIO);
+            return no_more_data.then_some(allocated);
+        }
+        let hole = unsafe { libc::lseek(fd, data, libc::SEEK_HOLE) };
+        if hole < data {
+            return None;
+        }
+        allocated += (hole - data) as u64;
+        pos = hole;
+    }
+    Some(allocated)
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "freebsd"
+)))]
+fn probe_data_extents(_path: &Path, _len: u64) -> Option<u64> {
+    None
+}
@@ -0,0 +1,97 @@
+use std::cmp::Ordering;
+
+/// Compares names so that embedded numbers order by value, e.g. `file2`
+/// before `file10`. Everything else compares byte-wise.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+    loop {
+        match (a.first(), b.first()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let (num_a, rest_a) = split_digits(a);
+                let (num_b, rest_b) = split_digits(b);
+                let (trim_a, trim_b) = (trim_zeros(num_a), trim_zeros(num_b));
+                let ord = trim_a
+                    .len()
+                    .cmp(&trim_b.len())
+                    .then_with(|| trim_a.cmp(trim_b))
+                    // `01` after `1` so the order stays total
+                    .then_with(|| num_a.len().cmp(&num_b.len()));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+                a = rest_a;
+                b = rest_b;
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(y);
+                }
+                a = &a[1..];
+                b = &b[1..];
+            }
+        }
+    }
+}
+
+fn split_digits(s: &[u8]) -> (&[u8], &[u8]) {
+    let end = s
+        .iter()
+        .position(|c| !c.is_ascii_digit())
+        .unwrap_or(s.len());
+    s.split_at(end)
+}
+
+fn trim_zeros(digits: &[u8]) -> &[u8] {
+    let start = digits
+        .iter()
+        .position(|&c| c != b'0')
+        .unwrap_or(digits.len());
+    &digits[start..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_order_by_value() {
+        assert_eq!(natural_cmp("file2", "file10"), Ordering::Less);
+        assert_eq!(natural_cmp("file10", "file2"), Ordering::Greater);
+        assert_eq!(natural_cmp("file10", "file10"), Ordering::Equal);
+    }
+
+    #[test]
+    fn leading_zeros_break_ties_only() {
+        assert_eq!(natural_cmp("a1", "a01"), Ordering::Less);
+        assert_eq!(natural_cmp("a01", "a2"), Ordering::Less);
+        assert_eq!(natural_cmp("a00", "a0"), Ordering::Greater);
+    }
+
+    #[test]
+    fn mixed_runs_compare_piece_by_piece() {
+        assert_eq!(natural_cmp("v1.10.2", "v1.9.9"), Ordering::Greater);
+        assert_eq!(natural_cmp("img12b", "img12a"), Ordering::Greater);
+        assert_eq!(natural_cmp("img12", "img12a"), Ordering::Less);
+        assert_eq!(natural_cmp("2x", "x2"), Ordering::Less);
+    }
+
+    #[test]
+    fn long_digit_runs_dont_overflow() {
+        let big = "n123456789012345678901234567890";
+        let bigger = "n123456789012345678901234567891";
+        assert!(big.len() > u64::MAX.to_string().len());
+        assert_eq!(natural_cmp(big, bigger), Ordering::Less);
+        assert_eq!(natural_cmp("n99999999999999999999", big), Ordering::Less);
+    }
+
+    #[test]
+    fn splits_and_trims_digit_runs() {
+        assert_eq!(split_digits(b"0042abc"), (&b"0042"[..], &b"abc"[..]));
+        assert_eq!(split_digits(b"7"), (&b"7"[..], &b""[..]));
+        assert_eq!(trim_zeros(b"0042"), b"42");
+        assert_eq!(trim_zeros(b"000"), b"");
+    }
+}
@@ -0,0 +1,210 @@
+use std::time::{Duration, SystemTime};
+
+/// Parses a duration such as `90`, `250ms`, `30s`, `5m`, `1.5h` or `2d`. A
+/// bare number is taken as seconds.
+pub fn parse_duration(input: &str) -> Result<Duration, String> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+    let value: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration `{input}`"))?;
+    let scale = match unit.trim() {
+        "ms" => 0.001,
+        "" | "s" => 1.0,
+        "m" => 60.0,
+        "h" => 60.0 * 60.0,
+        "d" => 24.0 * 60.0 * 60.0,
+        other => {
+            return Err(format!(
+                "unknown duration unit `{other}` (use ms, s, m, h or d)"
+            ))
+        }
+    };
+    Ok(Duration::from_secs_f64(value * scale))
+}
+
+/// Formats `n` with thousands separators, e.g. `12,345`.
+pub fn format_count(n: u64) -> String {
+    let digits = n.to_string();
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(digit);
+    }
+    out
+}
+
+/// How long ago `time` was, in its largest whole unit: "just now",
+/// "5 minutes ago", "3 months ago".
+pub fn format_age(time: SystemTime) -> String {
+    let Ok(age) = SystemTime::now().duration_since(time) else {
+        return "in the future".to_string();
+    };
+    let secs = age.as_secs();
+    let (count, unit) = match secs {
+        0..=59 => return "just now".to_string(),
+        60..=3_599 => (secs / 60, "minute"),
+        3_600..=86_399 => (secs / 3_600, "hour"),
+        86_400..=2_591_999 => (secs / 86_400, "day"),
+        2_592_000..=31_535_999 => (secs / 2_592_000, "month"),
+        _ => (secs / 31_536_000, "year"),
+    };
+    let plural = if count == 1 { "" } else { "s" };
+    format!("{} {}{} ago", count, unit, plural)
+}
+
+/// Formats `time` as `YYYY-MM-DD HH:MM UTC`.
+pub fn format_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (days, rest) = (secs / 86_400, secs % 86_400);
+
+    // Days since the epoch to a proleptic Gregorian date, counting years
+    // from March so the leap day falls at the end
+    let days = days as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest % 3600 / 60
+    )
+}
+
+/// Parses a size such as `512`, `4K`, `1.5G` or `10MB`. Single-letter and
+/// `iB` suffixes are binary (`K` = 1024), `B` suffixes are decimal
+/// (`KB` = 1000). A bare number is bytes.
+pub fn parse_size(input: &str) -> Result<u64, String> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+    let value: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size `{input}`"))?;
+    let unit = unit.trim().to_ascii_uppercase();
+    let (prefix, base) = match unit.strip_suffix("IB") {
+        Some(prefix) => (prefix, 1024.0),
+        None => match unit.strip_suffix('B') {
+            Some(prefix) if !prefix.is_empty() => (prefix, 1000.0),
+            _ => (unit.as_str(), 1024.0),
+        },
+    };
+    let exponent = match prefix {
+        "" | "B" => 0,
+        "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        "T" => 4,
+        "P" => 5,
+        _ => {
+            return Err(format!(
+                "unknown size unit in `{input}` (use K, M, G, T or P)"
+            ))
+        }
+    };
+    Ok((value * f64::powi(base, exponent)).round() as u64)
+}
+
+/// Which prefixes humanized sizes use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum UnitSystem {
+    /// Powers of 1000: kB, MB, GB.
+    #[default]
+    Decimal,
+    /// Powers of 1024: KiB, MiB, GiB.
+    Binary,
+}
+
+impl UnitSystem {
+    pub fn name(self) -> &'static str {
+        match self {
+            UnitSystem::Decimal => "decimal",
+            UnitSystem::Binary => "binary",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "decimal" => Some(UnitSystem::Decimal),
+            "binary" => Some(UnitSystem::Binary),
+            _ => None,
+        }
+    }
+
+    pub fn toggled(self) -> Self {
+        match self {
+            UnitSystem::Decimal => UnitSystem::Binary,
+            UnitSystem::Binary => UnitSystem::Decimal,
+        }
+    }
+}
+
+/// How byte counts are shown to the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeFormat {
+    /// Humanized units such as `4.20 MB` or `4.01 MiB`.
+    Human(UnitSystem),
+    /// Number of blocks of the given size, rounded up, like `du -B`.
+    Blocks(u64),
+}
+
+impl Default for SizeFormat {
+    fn default() -> Self {
+        SizeFormat::Human(UnitSystem::default())
+    }
+}
+
+impl SizeFormat {
+    pub fn format(self, bytes: u64) -> String {
+        match self {
+            SizeFormat::Human(UnitSystem::Decimal) => {
+                humansize::format_size(bytes, humansize::DECIMAL)
+            }
+            SizeFormat::Human(UnitSystem::Binary) => {
+                humansize::format_size(bytes, humansize::BINARY)
+            }
+            SizeFormat::Blocks(block_size) => format!("{} blk", bytes.div_ceil(block_size)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_human_sizes() {
+        assert_eq!(parse_size("1.5G"), Ok(1_610_612_736));
+        assert_eq!(parse_size("512K"), Ok(524_288));
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size(" 10MB "), Ok(10_000_000));
+        assert_eq!(parse_size("2MiB"), Ok(2_097_152));
+        assert_eq!(parse_size("7b"), Ok(7));
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("G").is_err());
+        assert!(parse_size("").is_err());
+    }
+}
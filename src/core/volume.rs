@@ -0,0 +1,77 @@
+use std::path::Path;
+
+/// Free inodes available to unprivileged users on the volume holding `path`.
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)] // `fsfilcnt_t` is 32-bit on some platforms
+pub fn free_inodes(path: &Path) -> Option<u64> {
+    statvfs(path).map(|st| st.f_favail as u64)
+}
+
+#[cfg(not(unix))]
+pub fn free_inodes(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Identifier of the filesystem holding the entry, used to spot mount points.
+#[cfg(unix)]
+pub fn device_id(md: &std::fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(md.dev())
+}
+
+#[cfg(not(unix))]
+pub fn device_id(_md: &std::fs::Metadata) -> Option<u64> {
+    None
+}
+
+/// The file's inode identity when it has more than one hard link.
+#[cfg(unix)]
+pub fn hardlink(md: &std::fs::Metadata) -> Option<super::Hardlink> {
+    use std::os::unix::fs::MetadataExt;
+    (md.nlink() > 1).then(|| super::Hardlink {
+        device: md.dev(),
+        inode: md.ino(),
+        links: md.nlink(),
+        counted: true,
+    })
+}
+
+#[cfg(not(unix))]
+pub fn hardlink(_md: &std::fs::Metadata) -> Option<super::Hardlink> {
+    None
+}
+
+/// Allocation unit assumed when the volume can't be queried.
+pub const DEFAULT_CLUSTER_SIZE: u64 = 4096;
+
+/// The volume's allocation unit (fragment size), which every file is rounded
+/// up to.
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)] // `c_ulong` is 32-bit on some platforms
+pub fn cluster_size(path: &Path) -> Option<u64> {
+    statvfs(path)
+        .map(|st| if st.f_frsize > 0 { st.f_frsize } else { st.f_bsize } as u64)
+        .filter(|&size| size > 0)
+}
+
+#[cfg(not(unix))]
+pub fn cluster_size(_path: &Path) -> Option<u64> {
+    None
+}
+
+#[cfg(unix)]
+fn statvfs(path: &Path) -> Option<libc::statvfs> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut st = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `c_path` is a valid NUL-terminated string and `st` is only read
+    // after statvfs reports success.
+    let rc = unsafe { libc::statvfs(c_path.as_ptr(), st.as_mut_ptr()) };
+    if rc == 0 {
+        Some(unsafe { st.assume_init() })
+    } else {
+        None
+    }
+}
@@ -0,0 +1,517 @@
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::SyncSender;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+use rayon::prelude::*;
+
+use super::{size, volume, Hardlink, ScanError, ScanOptions, SizeMode};
+
+/// Per-directory rules file, in `.gitignore` syntax, honored during the walk.
+pub const IGNORE_FILENAME: &str = ".dua-ignore";
+
+/// One file or directory as read by the walk, before any aggregation.
+#[derive(Debug, Clone)]
+pub struct WalkedEntry {
+    pub path: PathBuf,
+    pub size: u64,
+    pub allocated: u64,
+    /// `size` rounded up to the cluster size, if one was given.
+    pub rounded: u64,
+    pub is_dir: bool,
+    pub modified: Option<SystemTime>,
+    /// Filesystem the directory lives on; not recorded for files.
+    pub device: Option<u64>,
+    pub hardlink: Option<Hardlink>,
+    /// A followed link back to a directory above it, recorded in place of
+    /// walking that directory again.
+    pub symlink_loop: bool,
+}
+
+/// How a walk ended, apart from the entries it produced.
+#[derive(Debug, Clone, Default)]
+pub struct WalkOutcome {
+    pub timed_out: bool,
+    pub truncated: bool,
+    pub cancelled: bool,
+    pub errors: Vec<ScanError>,
+    /// Number of `IGNORE_FILENAME` files seen.
+    pub ignore_files: usize,
+    /// Size of the pool that read the metadata.
+    pub threads: usize,
+}
+
+/// Compiles user globs, or `None` when there are none to apply.
+fn compile_globs(patterns: &[String]) -> std::io::Result<Option<GlobSet>> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        builder.add(glob);
+    }
+    builder
+        .build()
+        .map(Some)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+}
+
+fn glob_matches(set: &GlobSet, path: &Path) -> bool {
+    set.is_match(path) || path.file_name().is_some_and(|name| set.is_match(name))
+}
+
+/// Whether a directory `depth` levels below the root lives on another
+/// filesystem than the root. An unknown device counts as the same one.
+fn crosses_device(depth: usize, device: Option<u64>, root_device: u64) -> bool {
+    depth > 0 && device.is_some_and(|device| device != root_device)
+}
+
+/// The link behind `err` if the walker refused to follow it because it
+/// leads back to a directory on the current path. The walker compares the
+/// device and inode of the target with each ancestor's, so a link to a
+/// sibling is still followed.
+fn loop_link(err: &ignore::Error) -> Option<&Path> {
+    match err {
+        ignore::Error::Loop { child, .. } => Some(child),
+        ignore::Error::WithPath { err, .. }
+        | ignore::Error::WithDepth { err, .. }
+        | ignore::Error::WithLineNumber { err, .. } => loop_link(err),
+        _ => None,
+    }
+}
+
+/// The three ways a file's size is counted.
+pub(crate) struct FileSizes {
+    pub size: u64,
+    pub allocated: u64,
+    pub rounded: u64,
+}
+
+pub(crate) fn file_sizes(path: &Path, md: &Metadata, options: &ScanOptions) -> FileSizes {
+    let mut size = match options.size_mode {
+        SizeMode::Allocated => size::allocated_size(md),
+        // Only a sparse file occupies less than its length; a small file's
+        // last block would otherwise count in full
+        SizeMode::Apparent if options.precise_sparse => {
+            size::precise_allocated_size(path, md).min(md.len())
+        }
+        SizeMode::Apparent => md.len(),
+    };
+    if options.xattrs {
+        size += size::xattr_size(path);
+    }
+    FileSizes {
+        size,
+        allocated: size::allocated_size(md),
+        rounded: options
+            .cluster_size
+            .map_or(size, |cluster| size.div_ceil(cluster) * cluster),
+    }
+}
+
+/// Sizes of any non-directory entry under the size policy: regular files
+/// always count, links and special files only when the policy says so.
+pub(crate) fn entry_sizes(path: &Path, md: &Metadata, options: &ScanOptions) -> FileSizes {
+    let file_type = md.file_type();
+    if file_type.is_file() {
+        return file_sizes(path, md, options);
+    }
+    let policy = options.size_policy;
+    let counted = if file_type.is_symlink() {
+        policy.symlinks
+    } else {
+        policy.special
+    };
+    if !counted {
+        return FileSizes {
+            size: 0,
+            allocated: 0,
+            rounded: 0,
+        };
+    }
+    // Sparse probing and xattrs would look through a link at its target
+    let size = match options.size_mode {
+        SizeMode::Allocated => size::allocated_size(md),
+        SizeMode::Apparent => md.len(),
+    };
+    FileSizes {
+        size,
+        allocated: size::allocated_size(md),
+        rounded: options
+            .cluster_size
+            .map_or(size, |cluster| size.div_ceil(cluster) * cluster),
+    }
+}
+
+/// Walks `root` in parallel, sending every entry to `tx` as soon as it's
+/// read. Entries arrive in no particular order. Sending blocks while the
+/// channel is full; if the receiver goes away the remaining entries are
+/// dropped.
+pub fn walk(
+    root: &Path,
+    options: &ScanOptions,
+    tx: SyncSender<WalkedEntry>,
+) -> std::io::Result<WalkOutcome> {
+    match options.threads {
+        // The directory walk itself is sequential; the limit applies to the
+        // pool reading metadata behind it
+        Some(threads) => rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .map_err(std::io::Error::other)?
+            .install(|| walk_in_pool(root, options, tx)),
+        None => walk_in_pool(root, options, tx),
+    }
+}
+
+fn walk_in_pool(
+    root: &Path,
+    options: &ScanOptions,
+    tx: SyncSender<WalkedEntry>,
+) -> std::io::Result<WalkOutcome> {
+    let include = compile_globs(&options.include)?;
+    let deadline = options.timeout.map(|t| Instant::now() + t);
+    let mut timed_out = false;
+    let mut cancelled = false;
+    let max_entries = options.max_entries.unwrap_or(usize::MAX);
+    let collected = AtomicUsize::new(0);
+    let truncated = AtomicBool::new(false);
+    let ignore_files = AtomicUsize::new(0);
+    let errors = Mutex::new(Vec::new());
+    let escaped_links = Arc::new(Mutex::new(Vec::new()));
+    let skipped_mounts = Arc::new(Mutex::new(Vec::new()));
+
+    let mut walker = WalkBuilder::new(root);
+    walker
+        .follow_links(options.follow_symlinks || options.contain_symlinks)
+        .hidden(false)
+        // Disk usage counts ignored files too unless asked otherwise
+        .git_ignore(options.use_gitignore)
+        .git_global(options.use_gitignore)
+        .git_exclude(options.use_gitignore)
+        .ignore(options.use_gitignore)
+        .add_custom_ignore_filename(IGNORE_FILENAME);
+    // The walker takes a single filter, so exclusion, link containment and
+    // staying on one filesystem share it; pruning here also keeps the walk
+    // out of the skipped trees
+    let exclude = compile_globs(&options.exclude)?;
+    let contain_root = if options.contain_symlinks {
+        Some(root.canonicalize()?)
+    } else {
+        None
+    };
+    let root_device = if options.one_file_system {
+        volume::device_id(&std::fs::metadata(root)?)
+    } else {
+        None
+    };
+    if exclude.is_some() || contain_root.is_some() || root_device.is_some() {
+        let escaped = Arc::clone(&escaped_links);
+        let mounts = Arc::clone(&skipped_mounts);
+        walker.filter_entry(move |dirent| {
+            if exclude
+                .as_ref()
+                .is_some_and(|set| glob_matches(set, dirent.path()))
+            {
+                return false;
+            }
+            // Only directories can be mount points; files skip the extra stat
+            let is_dir = dirent.file_type().is_some_and(|ft| ft.is_dir());
+            if let Some(root_device) = root_device.filter(|_| is_dir) {
+                let device = dirent.metadata().ok().and_then(|md| volume::device_id(&md));
+                if crosses_device(dirent.depth(), device, root_device) {
+                    mounts
+                        .lock()
+                        .unwrap()
+                        .push((dirent.path().to_path_buf(), device));
+                    return false;
+                }
+            }
+            let Some(root) = &contain_root else {
+                return true;
+            };
+            if !dirent.path_is_symlink() {
+                return true;
+            }
+            let inside = dirent
+                .path()
+                .canonicalize()
+                .is_ok_and(|target| target.starts_with(root));
+            if !inside {
+                escaped.lock().unwrap().push(dirent.path().to_path_buf());
+            }
+            inside
+        });
+    }
+
+    walker
+        .build()
+        .take_while(|_| {
+            if options.cancel.load(Ordering::Relaxed) {
+                cancelled = true;
+                return false;
+            }
+            if collected.load(Ordering::Relaxed) >= max_entries {
+                truncated.store(true, Ordering::Relaxed);
+                return false;
+            }
+            match deadline {
+                Some(deadline) if Instant::now() >= deadline => {
+                    timed_out = true;
+                    false
+                }
+                _ => true,
+            }
+        })
+        .par_bridge()
+        .filter_map(|entry| match entry {
+            Ok(dirent) => {
+                // The bridge reads ahead, so enforce the cap here as well
+                if collected.fetch_add(1, Ordering::Relaxed) >= max_entries {
+                    truncated.store(true, Ordering::Relaxed);
+                    return None;
+                }
+                if dirent.file_type().is_some_and(|ft| !ft.is_dir()) {
+                    if dirent.file_name() == IGNORE_FILENAME {
+                        ignore_files.fetch_add(1, Ordering::Relaxed);
+                    }
+                    if include
+                        .as_ref()
+                        .is_some_and(|set| !glob_matches(set, dirent.path()))
+                    {
+                        return None;
+                    }
+                    let md = match dirent.metadata() {
+                        Ok(md) => md,
+                        Err(err) => {
+                            errors.lock().unwrap().push(ScanError::from_walk(&err));
+                            return None;
+                        }
+                    };
+                    let sizes = entry_sizes(dirent.path(), &md, options);
+                    Some(WalkedEntry {
+                        size: sizes.size,
+                        allocated: sizes.allocated,
+                        rounded: sizes.rounded,
+                        is_dir: false,
+                        modified: md.modified().ok(),
+                        device: None,
+                        hardlink: volume::hardlink(&md),
+                        symlink_loop: false,
+                        path: dirent.into_path(),
+                    })
+                } else {
+                    let md = dirent.metadata().ok();
+                    let size = match &md {
+                        Some(md) if options.size_policy.dir_overhead && md.is_dir() => {
+                            size::dir_overhead(md)
+                        }
+                        _ => 0,
+                    };
+                    let modified = md.as_ref().and_then(|md| md.modified().ok());
+                    let device = md.as_ref().and_then(volume::device_id);
+                    Some(WalkedEntry {
+                        size,
+                        allocated: size,
+                        rounded: size,
+                        is_dir: true,
+                        modified,
+                        device,
+                        hardlink: None,
+                        symlink_loop: false,
+                        path: dirent.into_path(),
+                    })
+                }
+            }
+            Err(err) => match loop_link(&err) {
+                // Not an error: the loop is shown as an empty entry instead
+                Some(path) => {
+                    let modified = std::fs::symlink_metadata(path)
+                        .and_then(|md| md.modified())
+                        .ok();
+                    Some(WalkedEntry {
+                        path: path.to_path_buf(),
+                        size: 0,
+                        allocated: 0,
+                        rounded: 0,
+                        is_dir: false,
+                        modified,
+                        device: None,
+                        hardlink: None,
+                        symlink_loop: true,
+                    })
+                }
+                None => {
+                    errors.lock().unwrap().push(ScanError::from_walk(&err));
+                    None
+                }
+            },
+        })
+        .for_each(|entry| {
+            // A closed channel means the consumer stopped caring
+            let _ = tx.send(entry);
+        });
+
+    for path in escaped_links.lock().unwrap().drain(..) {
+        let modified = std::fs::symlink_metadata(&path)
+            .and_then(|md| md.modified())
+            .ok();
+        let _ = tx.send(WalkedEntry {
+            path,
+            size: 0,
+            allocated: 0,
+            rounded: 0,
+            is_dir: false,
+            modified,
+            device: None,
+            hardlink: None,
+            symlink_loop: false,
+        });
+    }
+
+    // Mount points stay visible, as empty directories on their own device
+    for (path, device) in skipped_mounts.lock().unwrap().drain(..) {
+        let modified = std::fs::metadata(&path).and_then(|md| md.modified()).ok();
+        let _ = tx.send(WalkedEntry {
+            path,
+            size: 0,
+            allocated: 0,
+            rounded: 0,
+            is_dir: true,
+            modified,
+            device,
+            hardlink: None,
+            symlink_loop: false,
+        });
+    }
+
+    Ok(WalkOutcome {
+        timed_out,
+        truncated: truncated.into_inner(),
+        cancelled,
+        errors: errors.into_inner().unwrap(),
+        ignore_files: ignore_files.into_inner(),
+        threads: rayon::current_num_threads(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    /// Paths `walk` reports under `root`, sorted.
+    fn walked(root: &Path, options: &ScanOptions) -> Vec<PathBuf> {
+        walked_with_outcome(root, options).1
+    }
+
+    fn walked_with_outcome(root: &Path, options: &ScanOptions) -> (WalkOutcome, Vec<PathBuf>) {
+        let (tx, rx) = std::sync::mpsc::sync_channel(16);
+        let outcome = std::thread::scope(|scope| {
+            let walker = scope.spawn(|| walk(root, options, tx));
+            let mut paths: Vec<PathBuf> = rx.iter().map(|entry| entry.path).collect();
+            paths.sort();
+            (walker.join().unwrap().unwrap(), paths)
+        });
+        assert!(!outcome.0.cancelled);
+        outcome
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn tiny_file_is_rounded_up_to_its_block() {
+        let dir = TempDir::new("tiny-file");
+        let path = dir.file("tiny", 10);
+        let md = std::fs::symlink_metadata(&path).unwrap();
+
+        let sizes = file_sizes(&path, &md, &ScanOptions::default());
+        assert!(sizes.size >= 512, "{} bytes", sizes.size);
+        assert_eq!(sizes.size % 512, 0);
+        assert_eq!(sizes.allocated, sizes.size);
+    }
+
+    #[test]
+    fn apparent_size_is_the_length() {
+        let dir = TempDir::new("apparent-size");
+        let path = dir.file("tiny", 10);
+        let md = std::fs::symlink_metadata(&path).unwrap();
+
+        let apparent = ScanOptions {
+            size_mode: SizeMode::Apparent,
+            ..ScanOptions::default()
+        };
+        assert_eq!(file_sizes(&path, &md, &apparent).size, 10);
+        let precise = ScanOptions {
+            precise_sparse: true,
+            ..apparent
+        };
+        assert_eq!(file_sizes(&path, &md, &precise).size, 10);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn precise_sparse_counts_only_written_data() {
+        let dir = TempDir::new("sparse-file");
+        let path = dir.path().join("sparse");
+        let file = std::fs::File::create(&path).unwrap();
+        file.set_len(64 << 20).unwrap();
+        let md = std::fs::symlink_metadata(&path).unwrap();
+
+        let options = ScanOptions {
+            size_mode: SizeMode::Apparent,
+            precise_sparse: true,
+            ..ScanOptions::default()
+        };
+        assert!(file_sizes(&path, &md, &options).size < md.len());
+    }
+
+    #[test]
+    fn thread_limit_still_reads_every_entry() {
+        let dir = TempDir::new("thread-limit");
+        for i in 0..50 {
+            dir.file(&format!("d{}/f{}", i % 5, i), 1);
+        }
+
+        let all = walked(dir.path(), &ScanOptions::default());
+        assert_eq!(all.len(), 1 + 5 + 50);
+        for threads in [1, 3] {
+            let options = ScanOptions {
+                threads: Some(threads),
+                ..ScanOptions::default()
+            };
+            let (outcome, paths) = walked_with_outcome(dir.path(), &options);
+            assert_eq!(outcome.threads, threads);
+            assert_eq!(paths, all);
+        }
+    }
+
+    #[test]
+    fn only_other_devices_below_the_root_are_pruned() {
+        assert!(crosses_device(1, Some(2), 1));
+        assert!(crosses_device(3, Some(2), 1));
+        assert!(!crosses_device(1, Some(1), 1));
+        assert!(!crosses_device(1, None, 1));
+        // The root itself is never pruned, whatever it reports
+        assert!(!crosses_device(0, Some(2), 1));
+    }
+
+    #[test]
+    fn one_file_system_keeps_a_single_device() {
+        let dir = TempDir::new("one-file-system");
+        dir.file("a/f", 1);
+        let options = ScanOptions {
+            one_file_system: true,
+            ..ScanOptions::default()
+        };
+        assert_eq!(
+            walked(dir.path(), &options),
+            walked(dir.path(), &ScanOptions::default())
+        );
+    }
+}
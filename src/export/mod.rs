@@ -0,0 +1,423 @@
+pub mod template;
+mod treemap;
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use serde_json::{json, Value};
+
+use crate::core::{
+    units::{SizeFormat, UnitSystem},
+    DirEntryInfo, ScanError,
+};
+
+use self::template::Template;
+
+pub use self::treemap::write_treemap_json;
+
+/// Writes scan errors as JSON lines of `{"path", "error", "kind"}`.
+pub fn write_errors<W: Write>(errors: &[ScanError], mut writer: W) -> io::Result<()> {
+    for err in errors {
+        let line = json!({
+            "path": err.path.to_string_lossy(),
+            "error": err.message,
+            "kind": format!("{:?}", err.kind),
+        });
+        writeln!(writer, "{}", line)?;
+    }
+    writer.flush()
+}
+
+/// Version of the `write_json` document. Adding fields keeps it; renaming,
+/// removing or changing the meaning of one bumps it, so consumers can tell
+/// a layout they don't know.
+pub const JSON_VERSION: u32 = 1;
+
+/// Writes the tree as one JSON document, `{"version", "generated_by",
+/// "root"}`, where `root` nests the entries with their path, sizes, counts
+/// and modification time (Unix seconds, or null); directories carry a
+/// `children` array. With a limit, only the entries it keeps (and the
+/// directories leading to them) are written. Indented with `pretty`, else on
+/// one line.
+pub fn write_json<W: Write>(
+    tree: &DirEntryInfo,
+    limit: Option<ResultLimit>,
+    pretty: bool,
+    writer: W,
+) -> io::Result<()> {
+    fn node(entry: &DirEntryInfo) -> Value {
+        let modified = entry
+            .modified
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|age| age.as_secs());
+        let mut value = json!({
+            "path": entry.path.to_string_lossy(),
+            "size": entry.size,
+            "allocated_size": entry.allocated_size,
+            "is_dir": entry.is_dir,
+            "file_count": entry.file_count,
+            "inodes": entry.inodes,
+            "modified": modified,
+        });
+        if entry.is_dir {
+            value["children"] = entry.children.iter().map(node).collect();
+        }
+        value
+    }
+
+    let document = json!({
+        "version": JSON_VERSION,
+        "generated_by": concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION")),
+        "root": node(&limited_tree(tree, limit)),
+    });
+    write_value(&document, pretty, writer)
+}
+
+/// Writes `value` and a newline, indented with `pretty` or on one line.
+pub(crate) fn write_value<W: Write>(value: &Value, pretty: bool, mut writer: W) -> io::Result<()> {
+    if pretty {
+        serde_json::to_writer_pretty(&mut writer, value)?;
+    } else {
+        serde_json::to_writer(&mut writer, value)?;
+    }
+    writeln!(writer)?;
+    writer.flush()
+}
+
+/// Writes a `size_bytes,human_size,path` header and one row per file,
+/// largest first, keeping only the `top` largest when given.
+pub fn write_csv<W: Write>(
+    tree: &DirEntryInfo,
+    format: SizeFormat,
+    top: Option<usize>,
+    mut writer: W,
+) -> io::Result<()> {
+    let mut files = tree.find_all(&|e| !e.is_dir);
+    files.sort_by_key(|f| std::cmp::Reverse(f.size));
+    files.truncate(top.unwrap_or(usize::MAX));
+
+    writeln!(writer, "size_bytes,human_size,path")?;
+    for file in files {
+        writeln!(
+            writer,
+            "{},{},{}",
+            file.size,
+            csv_field(&format.format(file.size)),
+            csv_field(&file.path.to_string_lossy())
+        )?;
+    }
+    writer.flush()
+}
+
+/// Quotes `field` if it holds a comma, quote or line break, doubling quotes.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// What to do with the scanned tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Browse it interactively
+    #[default]
+    Tui,
+    /// Print `<size>\t<path>` for every entry, like `du -a`
+    Du,
+    /// Print the tree as nested JSON
+    Json,
+    /// Print every file as CSV, largest first
+    Csv,
+    /// Print the tree as JSON with a treemap rectangle for every entry
+    TreemapJson,
+}
+
+/// Which entries a `--max-results` cap keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ResultScope {
+    /// The largest children of every directory
+    #[default]
+    PerDir,
+    /// The largest entries anywhere in the tree
+    Global,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ResultLimit {
+    pub max: usize,
+    pub scope: ResultScope,
+}
+
+/// An entry picked for export, with the size its percentage is relative to.
+pub struct ExportRow<'a> {
+    pub entry: &'a DirEntryInfo,
+    pub parent_size: u64,
+}
+
+/// Entries to export, parents before their children. With a limit, per-dir
+/// keeps the `max` largest children at each level (the root is always
+/// included) and global keeps the `max` largest entries below the root,
+/// largest first.
+pub fn select_entries(tree: &DirEntryInfo, limit: Option<ResultLimit>) -> Vec<ExportRow<'_>> {
+    fn walk<'a>(
+        entry: &'a DirEntryInfo,
+        parent_size: u64,
+        per_dir: Option<usize>,
+        rows: &mut Vec<ExportRow<'a>>,
+    ) {
+        rows.push(ExportRow { entry, parent_size });
+        let mut children: Vec<&DirEntryInfo> = entry.children.iter().collect();
+        if let Some(max) = per_dir {
+            children.sort_by_key(|c| std::cmp::Reverse(c.size));
+            children.truncate(max);
+        }
+        for child in children {
+            walk(child, entry.size, per_dir, rows);
+        }
+    }
+
+    let mut rows = Vec::new();
+    match limit {
+        None => walk(tree, tree.size, None, &mut rows),
+        Some(ResultLimit {
+            max,
+            scope: ResultScope::PerDir,
+        }) => walk(tree, tree.size, Some(max), &mut rows),
+        Some(ResultLimit {
+            max,
+            scope: ResultScope::Global,
+        }) => {
+            walk(tree, tree.size, None, &mut rows);
+            rows.remove(0);
+            rows.sort_by_key(|row| std::cmp::Reverse(row.entry.size));
+            rows.truncate(max);
+        }
+    }
+    rows
+}
+
+/// The tree cut down to the entries `limit` keeps, plus the directories
+/// leading to them so nested outputs stay connected. Sizes are left as
+/// scanned.
+pub(crate) fn limited_tree(
+    tree: &DirEntryInfo,
+    limit: Option<ResultLimit>,
+) -> Cow<'_, DirEntryInfo> {
+    fn prune(entry: &DirEntryInfo, kept: &HashSet<&Path>) -> DirEntryInfo {
+        DirEntryInfo {
+            path: entry.path.clone(),
+            children: entry
+                .children
+                .iter()
+                .filter(|child| kept.contains(child.path.as_path()))
+                .map(|child| prune(child, kept))
+                .collect(),
+            ..*entry
+        }
+    }
+
+    if limit.is_none() {
+        return Cow::Borrowed(tree);
+    }
+    let kept: HashSet<&Path> = select_entries(tree, limit)
+        .iter()
+        .flat_map(|row| row.entry.path.ancestors())
+        .collect();
+    Cow::Owned(prune(tree, &kept))
+}
+
+/// Writes one templated line per selected entry, with `{human_size}` in
+/// `format`.
+pub fn write_template<W: Write>(
+    tree: &DirEntryInfo,
+    template: &Template,
+    format: SizeFormat,
+    limit: Option<ResultLimit>,
+    mut writer: W,
+) -> io::Result<()> {
+    for row in select_entries(tree, limit) {
+        writeln!(
+            writer,
+            "{}",
+            template.render(row.entry, row.parent_size, format)
+        )?;
+    }
+    writer.flush()
+}
+
+/// Writes `<size>\t<path>` for every entry, children before their directory
+/// and the root last, as `du -a` does. Human sizes use du's compact style
+/// (`4.0K`, or `4.1k` for decimal units); block counts are bare numbers.
+/// With a limit, only the entries it keeps and their directories are listed.
+pub fn write_du<W: Write>(
+    tree: &DirEntryInfo,
+    format: SizeFormat,
+    limit: Option<ResultLimit>,
+    mut writer: W,
+) -> io::Result<()> {
+    fn walk<W: Write>(entry: &DirEntryInfo, format: SizeFormat, writer: &mut W) -> io::Result<()> {
+        for child in &entry.children {
+            walk(child, format, writer)?;
+        }
+        writeln!(
+            writer,
+            "{}\t{}",
+            du_size(entry.size, format),
+            entry.path.display()
+        )
+    }
+
+    walk(&limited_tree(tree, limit), format, &mut writer)?;
+    writer.flush()
+}
+
+fn du_size(bytes: u64, format: SizeFormat) -> String {
+    let (base, units) = match format {
+        SizeFormat::Blocks(block_size) => return bytes.div_ceil(block_size).to_string(),
+        SizeFormat::Human(UnitSystem::Binary) => (1024.0, ["K", "M", "G", "T", "P", "E"]),
+        SizeFormat::Human(UnitSystem::Decimal) => (1000.0, ["k", "M", "G", "T", "P", "E"]),
+    };
+    let mut value = bytes as f64;
+    let mut scaled = 0;
+    while value >= base && scaled < units.len() {
+        value /= base;
+        scaled += 1;
+    }
+    if scaled == 0 {
+        return bytes.to_string();
+    }
+    let unit = units[scaled - 1];
+    // du rounds up, with one decimal below 10
+    let tenths = (value * 10.0).ceil() / 10.0;
+    if tenths < 10.0 {
+        format!("{:.1}{}", tenths, unit)
+    } else {
+        format!("{:.0}{}", value.ceil(), unit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{dir_entry, file_entry};
+
+    fn tree() -> DirEntryInfo {
+        dir_entry(
+            "/r",
+            vec![
+                dir_entry(
+                    "/r/d",
+                    vec![file_entry("/r/d/a", 1500), file_entry("/r/d/b", 20)],
+                ),
+                file_entry("/r/c,d", 2_000_000),
+            ],
+        )
+    }
+
+    fn output(write: impl FnOnce(&mut Vec<u8>) -> io::Result<()>) -> String {
+        let mut out = Vec::new();
+        write(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn json_round_trips_sizes() {
+        let json = output(|out| write_json(&tree(), None, false, out));
+        let value = &serde_json::from_str::<Value>(&json).unwrap()["root"];
+
+        assert_eq!(value["path"], "/r");
+        assert_eq!(value["size"], 2_001_520);
+        assert_eq!(value["children"][0]["path"], "/r/d");
+        assert_eq!(value["children"][0]["size"], 1520);
+        assert_eq!(value["children"][0]["children"][1]["size"], 20);
+        assert_eq!(value["children"][1]["size"], 2_000_000);
+        assert!(value["children"][1].get("children").is_none());
+    }
+
+    #[test]
+    fn json_is_wrapped_in_a_versioned_envelope() {
+        let json = output(|out| write_json(&tree(), None, false, out));
+        let value: Value = serde_json::from_str(&json).unwrap();
+        let mut keys: Vec<&str> = value
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(|k| k.as_str())
+            .collect();
+        keys.sort();
+        assert_eq!(keys, ["generated_by", "root", "version"]);
+        assert_eq!(value["version"], 1);
+        assert_eq!(
+            value["generated_by"],
+            format!("disk-usage-tui {}", env!("CARGO_PKG_VERSION"))
+        );
+        assert!(value["root"].is_object());
+    }
+
+    #[test]
+    fn pretty_and_compact_json_hold_the_same_tree() {
+        let compact = output(|out| write_json(&tree(), None, false, out));
+        let pretty = output(|out| write_json(&tree(), None, true, out));
+        assert_eq!(compact.lines().count(), 1);
+        assert!(pretty.lines().count() > 1);
+        assert_eq!(
+            serde_json::from_str::<Value>(&compact).unwrap(),
+            serde_json::from_str::<Value>(&pretty).unwrap()
+        );
+
+        let compact = output(|out| write_treemap_json(&tree(), None, false, out));
+        let pretty = output(|out| write_treemap_json(&tree(), None, true, out));
+        assert_eq!(compact.lines().count(), 1);
+        assert_eq!(
+            serde_json::from_str::<Value>(&compact).unwrap(),
+            serde_json::from_str::<Value>(&pretty).unwrap()
+        );
+    }
+
+    #[test]
+    fn csv_lists_files_largest_first() {
+        let format = SizeFormat::Human(UnitSystem::Decimal);
+        let csv = output(|out| write_csv(&tree(), format, None, out));
+        assert_eq!(
+            csv,
+            "size_bytes,human_size,path\n\
+             2000000,2 MB,\"/r/c,d\"\n\
+             1500,1.50 kB,/r/d/a\n\
+             20,20 B,/r/d/b\n"
+        );
+
+        let top = output(|out| write_csv(&tree(), format, Some(1), out));
+        assert_eq!(top.lines().count(), 2);
+    }
+
+    #[test]
+    fn du_lists_children_first_and_honours_limits() {
+        let format = SizeFormat::Human(UnitSystem::Binary);
+        assert_eq!(
+            output(|out| write_du(&tree(), format, None, out)),
+            "1.5K\t/r/d/a\n20\t/r/d/b\n1.5K\t/r/d\n2.0M\t/r/c,d\n2.0M\t/r\n"
+        );
+
+        let per_dir = ResultLimit {
+            max: 1,
+            scope: ResultScope::PerDir,
+        };
+        assert_eq!(
+            output(|out| write_du(&tree(), SizeFormat::Blocks(1024), Some(per_dir), out)),
+            "1954\t/r/c,d\n1955\t/r\n"
+        );
+        let global = ResultLimit {
+            max: 3,
+            scope: ResultScope::Global,
+        };
+        assert_eq!(
+            output(|out| write_du(&tree(), SizeFormat::Blocks(1), Some(global), out)),
+            "1500\t/r/d/a\n1520\t/r/d\n2000000\t/r/c,d\n2001520\t/r\n"
+        );
+    }
+}
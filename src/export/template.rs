@@ -0,0 +1,170 @@
+use crate::core::{units::SizeFormat, DirEntryInfo};
+
+/// A row format such as `{path}\t{size}` with named placeholders, parsed once
+/// and rendered for every entry. `\t` and `\n` are unescaped and `{{`/`}}`
+/// produce literal braces.
+#[derive(Debug, Clone)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Literal(String),
+    Field(Field),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    Path,
+    Name,
+    Size,
+    HumanSize,
+    Pct,
+    Kind,
+    Inodes,
+}
+
+const FIELDS: &str = "path, name, size, human_size, pct, kind, inodes";
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "path" => Field::Path,
+            "name" => Field::Name,
+            "size" => Field::Size,
+            "human_size" => Field::HumanSize,
+            "pct" => Field::Pct,
+            "kind" => Field::Kind,
+            "inodes" => Field::Inodes,
+            _ => return None,
+        })
+    }
+}
+
+impl Template {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = input.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            closed = true;
+                            break;
+                        }
+                        name.push(c);
+                    }
+                    if !closed {
+                        return Err(format!("unclosed placeholder `{{{name}`"));
+                    }
+                    let field = Field::from_name(&name).ok_or_else(|| {
+                        format!("unknown placeholder `{{{name}}}` (expected one of: {FIELDS})")
+                    })?;
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Field(field));
+                }
+                '}' => return Err("unmatched `}` (use `}}` for a literal brace)".to_string()),
+                '\\' => match chars.peek() {
+                    Some('t') => {
+                        chars.next();
+                        literal.push('\t');
+                    }
+                    Some('n') => {
+                        chars.next();
+                        literal.push('\n');
+                    }
+                    _ => literal.push('\\'),
+                },
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Template { parts })
+    }
+
+    /// Renders `entry`; `parent_size` is what `{pct}` is relative to and
+    /// `format` writes `{human_size}`.
+    pub fn render(&self, entry: &DirEntryInfo, parent_size: u64, format: SizeFormat) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => out.push_str(text),
+                Part::Field(Field::Path) => out.push_str(&entry.path.to_string_lossy()),
+                Part::Field(Field::Name) => out.push_str(
+                    &entry
+                        .path
+                        .file_name()
+                        .map(|n| n.to_string_lossy())
+                        .unwrap_or_else(|| entry.path.to_string_lossy()),
+                ),
+                Part::Field(Field::Size) => out.push_str(&entry.size.to_string()),
+                Part::Field(Field::HumanSize) => out.push_str(&format.format(entry.size)),
+                Part::Field(Field::Pct) => {
+                    let pct = if parent_size == 0 {
+                        0.0
+                    } else {
+                        entry.size as f64 / parent_size as f64 * 100.0
+                    };
+                    out.push_str(&format!("{pct:.1}"));
+                }
+                Part::Field(Field::Kind) => out.push_str(if entry.is_dir { "dir" } else { "file" }),
+                Part::Field(Field::Inodes) => out.push_str(&entry.inodes.to_string()),
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::units::UnitSystem;
+    use crate::test_support::file_entry;
+
+    #[test]
+    fn human_size_follows_the_size_format() {
+        let template = Template::parse("{human_size}|{size}|{pct}").unwrap();
+        let entry = file_entry("/r/f", 2048);
+        let render = |format| template.render(&entry, 4096, format);
+
+        assert_eq!(
+            render(SizeFormat::Human(UnitSystem::Decimal)),
+            "2.05 kB|2048|50.0"
+        );
+        assert_eq!(
+            render(SizeFormat::Human(UnitSystem::Binary)),
+            "2 KiB|2048|50.0"
+        );
+        assert_eq!(render(SizeFormat::Blocks(512)), "4 blk|2048|50.0");
+    }
+
+    #[test]
+    fn parses_escapes_and_rejects_unknown_fields() {
+        let template = Template::parse("{{{name}}}\\t{kind}").unwrap();
+        let entry = file_entry("/r/f", 1);
+        assert_eq!(
+            template.render(&entry, 1, SizeFormat::Blocks(1)),
+            "{f}\tfile"
+        );
+        assert!(Template::parse("{nope}").is_err());
+        assert!(Template::parse("{path").is_err());
+        assert!(Template::parse("}").is_err());
+    }
+}
@@ -0,0 +1,176 @@
+use std::io::{self, Write};
+
+use serde_json::{json, Value};
+
+use crate::core::DirEntryInfo;
+
+use super::{limited_tree, write_value, ResultLimit};
+
+/// A node's rectangle within the unit square.
+#[derive(Debug, Clone, Copy)]
+struct Rect {
+    x: f64,
+    y: f64,
+    w: f64,
+    h: f64,
+}
+
+/// Writes the tree as nested JSON where every node carries the rectangle
+/// `x`, `y`, `w`, `h` it fills in a unit-square treemap, so a renderer only
+/// has to draw. Each directory's rectangle is sliced among its children in
+/// proportion to their sizes, largest first, alternating between vertical
+/// and horizontal cuts by depth. With a limit, only the entries it keeps
+/// (and the directories leading to them) are laid out, at their real share.
+/// Indented with `pretty`, else on one line.
+pub fn write_treemap_json<W: Write>(
+    tree: &DirEntryInfo,
+    limit: Option<ResultLimit>,
+    pretty: bool,
+    writer: W,
+) -> io::Result<()> {
+    let root = Rect {
+        x: 0.0,
+        y: 0.0,
+        w: 1.0,
+        h: 1.0,
+    };
+    let tree = layout(&limited_tree(tree, limit), root, 0);
+    write_value(&tree, pretty, writer)
+}
+
+fn layout(entry: &DirEntryInfo, rect: Rect, depth: usize) -> Value {
+    let mut node = json!({
+        "path": entry.path.to_string_lossy(),
+        "size": entry.size,
+        "x": rect.x,
+        "y": rect.y,
+        "w": rect.w,
+        "h": rect.h,
+    });
+    if !entry.is_dir {
+        return node;
+    }
+
+    let mut children: Vec<&DirEntryInfo> = entry.children.iter().collect();
+    children.sort_by_key(|c| std::cmp::Reverse(c.size));
+    let vertical_cuts = depth.is_multiple_of(2);
+    let mut offset = 0.0;
+    let laid_out: Vec<Value> = children
+        .into_iter()
+        .map(|child| {
+            let share = if entry.size == 0 {
+                0.0
+            } else {
+                child.size as f64 / entry.size as f64
+            };
+            let child_rect = if vertical_cuts {
+                Rect {
+                    x: rect.x + offset * rect.w,
+                    w: share * rect.w,
+                    ..rect
+                }
+            } else {
+                Rect {
+                    y: rect.y + offset * rect.h,
+                    h: share * rect.h,
+                    ..rect
+                }
+            };
+            offset += share;
+            layout(child, child_rect, depth + 1)
+        })
+        .collect();
+    node["children"] = Value::Array(laid_out);
+    node
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{dir_entry, file_entry};
+
+    const UNIT: Rect = Rect {
+        x: 0.0,
+        y: 0.0,
+        w: 1.0,
+        h: 1.0,
+    };
+
+    fn rect(node: &Value) -> [f64; 4] {
+        ["x", "y", "w", "h"].map(|key| node[key].as_f64().unwrap())
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    fn tree() -> DirEntryInfo {
+        dir_entry(
+            "/r",
+            vec![
+                dir_entry(
+                    "/r/d",
+                    vec![file_entry("/r/d/a", 30), file_entry("/r/d/b", 10)],
+                ),
+                file_entry("/r/f", 60),
+            ],
+        )
+    }
+
+    #[test]
+    fn children_tile_their_parent_largest_first() {
+        let root = layout(&tree(), UNIT, 0);
+        let children = root["children"].as_array().unwrap();
+        assert_eq!(children[0]["path"], "/r/f");
+
+        let mut x = 0.0;
+        let mut area = 0.0;
+        for child in children {
+            let [cx, cy, w, h] = rect(child);
+            // Side by side, with no gap or overlap
+            assert!(close(cx, x));
+            assert!(close(cy, 0.0) && close(h, 1.0));
+            x += w;
+            area += w * h;
+        }
+        assert!(close(x, 1.0));
+        assert!(close(area, 1.0));
+        assert!(close(rect(&children[0])[2], 0.6));
+    }
+
+    #[test]
+    fn cuts_alternate_between_axes_by_depth() {
+        let root = layout(&tree(), UNIT, 0);
+        let dir = &root["children"][1];
+        let [dx, dy, dw, dh] = rect(dir);
+        assert!(close(dw, 0.4) && close(dh, 1.0));
+
+        // One level down the directory is cut horizontally
+        let grandchildren = dir["children"].as_array().unwrap();
+        let [ax, ay, aw, ah] = rect(&grandchildren[0]);
+        let [bx, by, bw, bh] = rect(&grandchildren[1]);
+        assert!(close(ax, dx) && close(bx, dx));
+        assert!(close(aw, dw) && close(bw, dw));
+        assert!(close(ay, dy) && close(ah, 0.75));
+        assert!(close(by, ay + ah) && close(bh, 0.25));
+    }
+
+    #[test]
+    fn an_empty_directory_gives_its_children_no_area() {
+        let empty = dir_entry("/r", vec![file_entry("/r/a", 0), file_entry("/r/b", 0)]);
+        let root = layout(&empty, UNIT, 0);
+        for child in root["children"].as_array().unwrap() {
+            let [_, _, w, h] = rect(child);
+            assert_eq!(w * h, 0.0);
+        }
+        assert!(close(rect(&root)[2], 1.0));
+    }
+
+    #[test]
+    fn writes_the_layout_as_one_document() {
+        let mut out = Vec::new();
+        write_treemap_json(&tree(), None, false, &mut out).unwrap();
+        let value: Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(value, layout(&tree(), UNIT, 0));
+    }
+}
@@ -0,0 +1,52 @@
+//! The system clipboard, behind `arboard`.
+
+use std::path::Path;
+
+/// A connection to the system clipboard. On X11 and Wayland the copied
+/// text is served by this process, so keep it for the whole session.
+pub struct Clipboard {
+    inner: arboard::Clipboard,
+}
+
+impl Clipboard {
+    /// Fails without a clipboard to talk to, e.g. over SSH or on a console.
+    pub fn new() -> Result<Clipboard, String> {
+        arboard::Clipboard::new()
+            .map(|inner| Clipboard { inner })
+            .map_err(|err| err.to_string())
+    }
+
+    /// Puts `path` on the clipboard as plain text.
+    pub fn copy_path(&mut self, path: &Path) -> Result<(), String> {
+        let text = path_text(path)?;
+        self.inner.set_text(text).map_err(|err| err.to_string())
+    }
+}
+
+/// `path` as clipboard text, unquoted. Paths that aren't valid UTF-8 can't
+/// be copied without changing them.
+pub fn path_text(path: &Path) -> Result<&str, String> {
+    path.to_str()
+        .ok_or_else(|| "the path isn't valid UTF-8".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_paths_with_spaces_as_written() {
+        let path = Path::new("/home/me/My Documents/a, b.txt");
+        assert_eq!(path_text(path), Ok("/home/me/My Documents/a, b.txt"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_paths_that_are_not_utf8() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = Path::new(OsStr::from_bytes(b"/tmp/caf\xe9/file"));
+        assert!(path_text(path).is_err());
+    }
+}
@@ -0,0 +1,94 @@
+//! Handing entries over to other programs on the desktop.
+
+pub mod clipboard;
+
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// The command that shows `path` in the platform's file manager: selected
+/// in its folder where the file manager supports that, otherwise by opening
+/// the folder it's in (or the directory itself).
+pub fn reveal_command(path: &Path) -> Command {
+    if cfg!(target_os = "macos") {
+        let mut command = Command::new("open");
+        command.arg("-R").arg(path);
+        command
+    } else if cfg!(windows) {
+        // Explorer wants `/select,` and the path as a single argument
+        let mut select = std::ffi::OsString::from("/select,");
+        select.push(path);
+        let mut command = Command::new("explorer");
+        command.arg(select);
+        command
+    } else {
+        // xdg-open would open a file in its application rather than show it
+        let folder = if path.is_dir() {
+            path
+        } else {
+            path.parent().unwrap_or(path)
+        };
+        let mut command = Command::new("xdg-open");
+        command.arg(folder);
+        command
+    }
+}
+
+/// Shows `path` in the file manager without waiting for it. Fails only if
+/// the command couldn't be started.
+pub fn reveal(path: &Path) -> std::io::Result<()> {
+    let mut command = reveal_command(path);
+    // Keep its output off the interface
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|err| {
+            let program = command.get_program().to_string_lossy();
+            std::io::Error::new(err.kind(), format!("{}: {}", program, err))
+        })?;
+    // Reap it whenever it exits, so it doesn't linger as a zombie
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    fn args(command: &Command) -> Vec<String> {
+        command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn reveal_command_suits_the_platform() {
+        let dir = TempDir::new("reveal");
+        let file = dir.file("sub/report.txt", 1);
+        let command = reveal_command(&file);
+        let program = command.get_program().to_string_lossy().into_owned();
+
+        if cfg!(target_os = "macos") {
+            assert_eq!(program, "open");
+            assert_eq!(
+                args(&command),
+                ["-R".to_string(), file.display().to_string()]
+            );
+        } else if cfg!(windows) {
+            assert_eq!(program, "explorer");
+            assert_eq!(args(&command), [format!("/select,{}", file.display())]);
+        } else {
+            assert_eq!(program, "xdg-open");
+            // A file's folder is opened, a directory itself
+            let folder = dir.path().join("sub");
+            assert_eq!(args(&command), [folder.display().to_string()]);
+            assert_eq!(
+                args(&reveal_command(&folder)),
+                [folder.display().to_string()]
+            );
+        }
+    }
+}
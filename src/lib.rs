@@ -0,0 +1,249 @@
+//! Scanner and interface behind the `disk-usage-tui` binary.
+//!
+//! `core::build_tree` scans a directory; `run_tui` shows the result and
+//! returns the path the user ended on, if they didn't abandon it with
+//! Ctrl-C, so other programs can embed the browser and act on the choice.
+
+pub mod cache;
+pub mod config;
+pub mod core;
+pub mod export;
+pub mod integration;
+pub mod ui;
+
+#[cfg(test)]
+mod test_support;
+
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use crossterm::{
+    event::{
+        DisableMouseCapture, EnableMouseCapture, KeyCode, KeyModifiers, MouseButton, MouseEventKind,
+    },
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use tui::{backend::CrosstermBackend, Terminal};
+
+use crate::{
+    core::DirEntryInfo,
+    ui::{
+        app::{App, PromptKind},
+        event::{self, Action, Events},
+        ui::{draw_ui, plain_listing},
+    },
+};
+
+/// Browses `root` on stdout with default settings and returns the path the
+/// user quit on, or `None` if they left with Ctrl-C.
+pub fn run_tui(root: DirEntryInfo) -> Result<Option<PathBuf>> {
+    run_tui_on(&mut App::new(root), std::io::stdout())
+}
+
+/// Owns the terminal while the interface runs and puts it back on drop, so
+/// an error or panic doesn't leave the shell in raw mode.
+struct TerminalGuard<W: Write> {
+    terminal: Terminal<CrosstermBackend<W>>,
+}
+
+impl<W: Write> Drop for TerminalGuard<W> {
+    fn drop(&mut self) {
+        // Nothing sensible to do if restoring fails; keep going with the rest
+        let _ = disable_raw_mode();
+        let _ = execute!(
+            self.terminal.backend_mut(),
+            LeaveAlternateScreen,
+            DisableMouseCapture
+        );
+        let _ = self.terminal.show_cursor();
+    }
+}
+
+/// Takes over the terminal behind `out` (raw mode, alternate screen), runs
+/// `app` until the user quits, and restores the terminal afterwards.
+pub fn run_tui_on<W: Write>(app: &mut App, mut out: W) -> Result<Option<PathBuf>> {
+    enable_raw_mode()?;
+    if let Err(err) = execute!(out, EnterAlternateScreen, EnableMouseCapture) {
+        let _ = disable_raw_mode();
+        return Err(err.into());
+    }
+    let mut guard = TerminalGuard {
+        terminal: Terminal::new(CrosstermBackend::new(out))?,
+    };
+    let events = Events::new(app.tick_rate);
+
+    run_app(&mut guard.terminal, app, &events)
+}
+
+/// Runs the interface on a terminal the caller has already set up, until the
+/// user quits. Returns the current directory, or the selected entry when
+/// quitting with `P`; `None` when quitting with Ctrl-C, which picks nothing.
+pub fn run_app<W: Write>(
+    terminal: &mut Terminal<CrosstermBackend<W>>,
+    app: &mut App,
+    events: &Events,
+) -> Result<Option<PathBuf>> {
+    terminal.clear()?;
+    loop {
+        app.poll_scan();
+
+        // Draw UI
+        terminal.draw(|f| draw_ui(f, app))?;
+
+        // Handle events; a closed channel means the input thread gave up
+        let key = match events.next() {
+            Ok(event::Event::Input(key)) => key,
+            Ok(event::Event::Mouse(mouse)) => {
+                // Prompts and the delete confirmation wait for the keyboard
+                if app.prompt.is_some() || app.confirm_delete.is_some() {
+                    continue;
+                }
+                app.message = None;
+                if app.show_help {
+                    app.show_help = false;
+                    continue;
+                }
+                match mouse.kind {
+                    MouseEventKind::ScrollUp => app.move_selection(-1),
+                    MouseEventKind::ScrollDown => app.move_selection(1),
+                    MouseEventKind::Down(MouseButton::Left) => app.click(mouse.column, mouse.row),
+                    _ => {}
+                }
+                continue;
+            }
+            // Drawing picks up the new size and lays everything out again
+            Ok(event::Event::Resize(..)) | Ok(event::Event::Tick) => continue,
+            // The input thread keeps retrying; just let the user know
+            Ok(event::Event::Error(err)) => {
+                app.message = Some(format!("Input error: {}", err));
+                continue;
+            }
+            Err(_) => anyhow::bail!("terminal input stopped responding"),
+        };
+        app.message = None;
+        app.alert = false;
+        if let Some(prompt) = &mut app.prompt {
+            let filtering = prompt.kind == PromptKind::Filter;
+            match key.code {
+                KeyCode::Enter => app.submit_prompt(),
+                KeyCode::Esc => {
+                    app.prompt = None;
+                    if filtering {
+                        app.set_filter(None);
+                    }
+                }
+                KeyCode::Backspace => {
+                    prompt.input.pop();
+                }
+                KeyCode::Char(c) => prompt.input.push(c),
+                _ => {}
+            }
+            // The listing follows the query as it's typed
+            if let Some(prompt) = app.prompt.as_ref().filter(|_| filtering) {
+                let query = prompt.input.clone();
+                app.set_filter(Some(query));
+            }
+            continue;
+        }
+        if app.show_help {
+            app.show_help = false;
+            continue;
+        }
+        if app.confirm_delete.is_some() {
+            if key.code == KeyCode::Char('y') {
+                app.delete_selected();
+            } else {
+                app.confirm_delete = None;
+                app.message = Some("Nothing deleted".to_string());
+            }
+            continue;
+        }
+        if key.code == KeyCode::Esc && app.stop_delete() {
+            continue;
+        }
+        // Raw mode turns Ctrl-C into a key press instead of a signal. The
+        // first one during the scan or a delete stops it and keeps what's
+        // there; a second one, or any after, quits
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            if app.stop_live_scan() || app.stop_delete() {
+                continue;
+            }
+            return Ok(None);
+        }
+        if let Some(action) = event::handle_key_event(&app.keymap, key.code) {
+            if app.read_only && action.is_destructive() {
+                app.message = Some("Read-only mode: nothing on disk is changed".to_string());
+                continue;
+            }
+            match action {
+                Action::Quit => return Ok(Some(app.current_node.path.clone())),
+                Action::QuitWithSelected => {
+                    let selected = app.selected_entry().map(|c| c.path.clone());
+                    return Ok(selected.or_else(|| Some(app.current_node.path.clone())));
+                }
+                Action::ToggleSort => app.toggle_sort(),
+                Action::ToggleSortDirection => app.toggle_sort_direction(),
+                Action::ToggleExpand => app.toggle_expanded(),
+                Action::GoTo => app.open_prompt(PromptKind::GoTo),
+                Action::Search => app.open_prompt(PromptKind::Filter),
+                Action::CycleMinSize => app.cycle_min_size(),
+                Action::InvertFilter => app.toggle_invert_filter(),
+                Action::ToggleRollup => app.toggle_rollup(),
+                Action::ZeroByteFiles => app.toggle_zero_byte_view(),
+                Action::Hardlinks => app.toggle_hardlink_view(),
+                Action::LargestDirs => app.toggle_largest_dirs_view(),
+                Action::LargestFiles => app.toggle_largest_files_view(),
+                Action::OpenRoot => app.open_prompt(PromptKind::Open),
+                Action::ToggleDetailed => app.detailed = !app.detailed,
+                Action::ToggleTreemap => app.show_treemap = !app.show_treemap,
+                Action::CollapseChains => app.collapse_chains = !app.collapse_chains,
+                Action::DirectSizes => app.direct_sizes = !app.direct_sizes,
+                Action::Delete => app.request_delete(),
+                Action::Reveal => app.reveal_selected(),
+                Action::CopyPath => app.copy_selected(),
+                Action::ToggleUnits => {
+                    if let Some(units) = app.toggle_units() {
+                        let root = &app.stack[0].path;
+                        app.message = Some(match config::save_units(root, units) {
+                            Ok(()) => {
+                                format!("Using {} units for {}", units.name(), root.display())
+                            }
+                            Err(err) => format!("Couldn't save unit preference: {}", err),
+                        });
+                    }
+                }
+                Action::RefreshSizes => app.refresh_sizes(),
+                Action::Rescan => app.rescan_current(),
+                Action::RescanRoot => app.rescan_root(),
+                Action::ShowCompleteness => app.show_completeness = !app.show_completeness,
+                Action::ToggleHelp => app.show_help = true,
+                Action::ShowErrors => app.toggle_errors_view(),
+                Action::MoveSelection(delta) => app.move_selection(delta),
+                Action::MovePage(pages) => app.move_page(pages),
+                Action::SelectFirst => app.select_first(),
+                Action::SelectLast => app.select_last(),
+                Action::NavigateIn => {
+                    app.navigate_into();
+                }
+                Action::NavigateOut => {
+                    app.navigate_out();
+                }
+                Action::DumpListing => {
+                    // Print on the main screen so screen readers see plain lines
+                    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+                    let out = terminal.backend_mut();
+                    for line in plain_listing(app) {
+                        write!(out, "{}\r\n", line)?;
+                    }
+                    write!(out, "\r\nPress any key to return.\r\n")?;
+                    out.flush()?;
+                    while !matches!(events.next()?, event::Event::Input(_)) {}
+                    execute!(terminal.backend_mut(), EnterAlternateScreen)?;
+                    terminal.clear()?;
+                }
+            }
+        }
+    }
+}
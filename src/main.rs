@@ -1,101 +1,620 @@
-mod core;
-mod ui;
-
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
 use std::time::Duration;
 
 use anyhow::Result;
-use clap::{arg, command, Parser};
-use crossterm::{
-    event::{DisableMouseCapture, EnableMouseCapture},
-    execute,
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
-};
-use tui::{backend::CrosstermBackend, Terminal};
-
-use crate::{
-    core::build_tree,
-    ui::{
-        app::App,
-        event::{self, Action, Events},
-        ui::draw_ui,
+use clap::Parser;
+
+use disk_usage_tui::{
+    cache, config,
+    core::{
+        self, build_tree,
+        units::{format_timestamp, parse_duration, parse_size, SizeFormat, UnitSystem},
+        ScanError, ScanOptions, SizeMode, SizePolicy, TreeBuilder,
     },
+    export::{self, template::Template, OutputFormat, ResultLimit, ResultScope},
+    run_tui_on,
+    ui::{app::App, glyphs::Glyphs, keymap::Keymap, theme::Theme},
 };
 
 #[derive(Parser, Debug)]
 #[command(version, about = "Disk Usage TUI Analyzer")]
 struct Cli {
-    /// Root directory to scan
+    /// Directories to scan; several are shown side by side under the
+    /// directory that holds them all
     #[arg(default_value = ".")]
-    root: PathBuf,
+    root: Vec<PathBuf>,
 
     /// Follow symbolic links
     #[arg(long)]
     follow_symlinks: bool,
+
+    /// Follow symbolic links only when they point inside the root
+    #[arg(long)]
+    follow_symlinks_careful: bool,
+
+    /// Stay on the root's filesystem: other mounts show up empty instead of
+    /// being scanned, like `du -x`
+    #[arg(long, short = 'x')]
+    one_file_system: bool,
+
+    /// Use the root path as given instead of resolving it through symlinks,
+    /// here and for roots opened from the interface
+    #[arg(long)]
+    no_canonicalize: bool,
+
+    /// Stop scanning after this long (e.g. 30s, 5m) and show a partial tree
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    scan_timeout: Option<Duration>,
+
+    /// Redraw the interface this often without input (e.g. 250ms); the
+    /// default is 100ms
+    #[arg(long, value_name = "DURATION", value_parser = parse_tick_rate)]
+    tick_rate: Option<Duration>,
+
+    /// Only show this many levels below the root (0 shows the root alone);
+    /// deeper entries still count toward the totals
+    #[arg(long, value_name = "N")]
+    max_depth: Option<usize>,
+
+    /// Stop scanning after this many entries and show a partial tree
+    #[arg(long, value_name = "N")]
+    max_entries: Option<usize>,
+
+    /// Scan in the background: lower CPU priority (and I/O priority on
+    /// Linux) and use half the cores
+    #[arg(long)]
+    nice: bool,
+
+    /// Read file metadata with this many threads (0 for one per core); a
+    /// single thread can be faster on spinning disks. Overrides the count
+    /// --nice picks
+    #[arg(long, value_name = "N")]
+    threads: Option<usize>,
+
+    /// Show inode counts per directory and the volume's free inodes
+    #[arg(long)]
+    inodes: bool,
+
+    /// Show how long each top-level directory took to scan, to find slow
+    /// mounts or network shares
+    #[arg(long)]
+    scan_times: bool,
+
+    /// Show each entry's average file size (also shown while sorting by it)
+    #[arg(long)]
+    avg_size: bool,
+
+    /// Show how many files each entry holds, counting every level below it
+    /// (also shown while sorting by it)
+    #[arg(long)]
+    file_count: bool,
+
+    /// Show when each entry was last modified, or for directories anything
+    /// in them (also shown while sorting by it)
+    #[arg(long)]
+    modified: bool,
+
+    /// Add an "On Disk" column with each file rounded up to the volume's
+    /// allocation unit, like Finder's "Size on disk"
+    #[arg(long)]
+    size_on_disk: bool,
+
+    /// Unit system for this session; otherwise the one last picked for this
+    /// root with `u` is used
+    #[arg(long, value_enum)]
+    units: Option<UnitSystem>,
+
+    /// Show sizes as block counts instead of humanized bytes
+    #[arg(long)]
+    blocks: bool,
+
+    /// Draw with ASCII only, for terminals without Unicode (detected from
+    /// TERM and the locale otherwise)
+    #[arg(long)]
+    ascii: bool,
+
+    /// How to color the listing: by size (heat), by kind (classic) or not
+    /// at all (plain)
+    #[arg(long, value_enum, value_name = "THEME")]
+    theme: Option<Theme>,
+
+    /// Don't color the listing; same as --theme plain. Also the default
+    /// when NO_COLOR is set
+    #[arg(long, conflicts_with = "theme")]
+    no_color: bool,
+
+    /// Show runs of directories with a single subdirectory as one row,
+    /// e.g. `a/b/c` (toggle with `c`)
+    #[arg(long)]
+    collapse_chains: bool,
+
+    /// List entries on two lines, full name above the figures (toggle with `w`)
+    #[arg(long)]
+    detailed: bool,
+
+    /// Block size used by --blocks (e.g. 512, 4K)
+    #[arg(long, value_name = "SIZE", default_value = "512", value_parser = parse_block_size)]
+    block_size: u64,
+
+    /// Highlight files modified within this long (e.g. 5m, 1h)
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    recent: Option<Duration>,
+
+    /// Browse only: refuse delete and anything else that changes files
+    #[arg(long, visible_alias = "read-only")]
+    safe: bool,
+
+    /// Draw directories larger than this (e.g. 10G) as warnings, and flag
+    /// the ones that grow past it when sizes are refreshed with `R`
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    warn_at: Option<u64>,
+
+    /// Hide entries smaller than this (e.g. 10M) from the listing; `m`
+    /// changes it while browsing
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    min_size: Option<u64>,
+
+    /// Write scan errors as JSON lines to this file (`-` for stderr). Other
+    /// outputs than the interface write them to stderr by default
+    #[arg(long, value_name = "FILE")]
+    errors_to: Option<PathBuf>,
+
+    /// Count logical file lengths, as `ls -l` shows them, instead of the
+    /// blocks allocated on disk
+    #[arg(long)]
+    apparent_size: bool,
+
+    /// With --apparent-size, measure the space sparse files really use
+    /// instead of their length (slower)
+    #[arg(long)]
+    precise_sparse: bool,
+
+    /// Add extended attributes (and macOS resource forks) to file sizes;
+    /// slower, as each file needs extra syscalls
+    #[arg(long)]
+    xattrs: bool,
+
+    /// Count the blocks directories themselves use, like `du` (Unix only);
+    /// same as adding `dir-overhead` to --size-policy
+    #[arg(long)]
+    count_dir_overhead: bool,
+
+    /// What adds up to a directory's size: `regular` files only (default),
+    /// `du` for everything `du` counts, or regular files plus a list of
+    /// `symlinks`, `dir-overhead`, `special` (e.g. `regular,symlinks`)
+    #[arg(long, value_name = "POLICY", value_parser = SizePolicy::parse)]
+    size_policy: Option<SizePolicy>,
+
+    /// Only count files matching this glob (repeatable); directories are
+    /// still walked
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// Skip entries matching this glob, and everything below them
+    /// (repeatable); tried against the full path and the bare name
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Leave out what .gitignore, .ignore and git's exclude files ignore,
+    /// such as build output. Off by default: everything counts, and only
+    /// .dua-ignore files and --exclude skip entries. .gitignore rules apply
+    /// inside git repositories only, as in git
+    #[arg(long)]
+    use_gitignore: bool,
+
+    /// On quit, print the current directory to stdout (`P` prints the
+    /// selected entry instead); the interface is drawn on stderr
+    #[arg(long)]
+    print_path_on_exit: bool,
+
+    /// Browse the tree, or print it in another tool's format
+    #[arg(long, value_enum, default_value_t)]
+    output: OutputFormat,
+
+    /// Indent --output json and treemap-json for reading, instead of
+    /// printing them on one line for scripts
+    #[arg(long)]
+    json_pretty: bool,
+
+    /// Show the last cached scan of the root instead of walking it again,
+    /// if it was made with the same options and the root's modification
+    /// time hasn't changed; new scans are cached
+    #[arg(long)]
+    reuse_cache: bool,
+
+    /// Walk the root even if a cached scan exists, and cache the result
+    #[arg(long)]
+    refresh: bool,
+
+    /// List past scans (time, total, root) and exit
+    #[arg(long, conflicts_with = "clear_history")]
+    history: bool,
+
+    /// Forget the recorded scans and exit
+    #[arg(long)]
+    clear_history: bool,
+
+    /// Print one line per entry using this template instead of starting the
+    /// interface, e.g. "{path}\t{size}\t{pct}". Placeholders: path, name,
+    /// size, human_size, pct, kind, inodes
+    #[arg(long, value_name = "TEMPLATE", value_parser = Template::parse, conflicts_with = "output")]
+    format_template: Option<Template>,
+
+    /// In non-interactive output, keep only the N largest entries (with
+    /// --output csv, the N largest files); nested outputs keep the
+    /// directories leading to them
+    #[arg(long, visible_alias = "top", value_name = "N")]
+    max_results: Option<usize>,
+
+    /// In non-interactive output, list entries as the interface would: each
+    /// directory's children largest first, leaving out those under
+    /// --min-size. Otherwise every entry is written, in path order
+    #[arg(long)]
+    export_as_viewed: bool,
+
+    /// Whether --max-results applies to each directory's children or to the
+    /// whole tree
+    #[arg(long, value_enum, default_value_t)]
+    max_results_scope: ResultScope,
+}
+
+fn parse_block_size(input: &str) -> Result<u64, String> {
+    match parse_size(input)? {
+        0 => Err("block size must be at least one byte".to_string()),
+        size => Ok(size),
+    }
+}
+
+fn parse_tick_rate(input: &str) -> Result<Duration, String> {
+    match parse_duration(input)? {
+        rate if rate.is_zero() => Err("tick rate must be above zero".to_string()),
+        rate => Ok(rate),
+    }
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
-    let root = cli.root.canonicalize()?;
+    if cli.history {
+        let format = SizeFormat::Human(cli.units.unwrap_or_default());
+        for entry in config::load_history() {
+            println!(
+                "{}  {:>10}  {}",
+                format_timestamp(entry.time),
+                format.format(entry.size),
+                entry.root.display()
+            );
+        }
+        return Ok(());
+    }
+    if cli.clear_history {
+        config::clear_history()?;
+        return Ok(());
+    }
+
+    let roots = resolve_roots(&cli.root, cli.no_canonicalize);
+    if roots.is_empty() {
+        anyhow::bail!("none of the roots can be scanned");
+    }
+    let multiple = roots.len() > 1;
+    let root = common_ancestor(&roots);
+
+    let glyphs = Glyphs::detect(cli.ascii);
+    // Before scanning, so a broken file doesn't waste a long scan
+    let keymap = Keymap::load().map_err(anyhow::Error::msg)?;
 
     // Setup progress bar
     let pb = indicatif::ProgressBar::new_spinner();
     pb.set_style(
-        indicatif::ProgressStyle::with_template("{spinner} Scanning {msg}")?
-            .tick_strings(&["⠁", "⠂", "⠄", "⡀", "⢀", "⠠", "⠐", "⠈"]),
+        indicatif::ProgressStyle::with_template("{spinner} {msg}")?.tick_strings(glyphs.spinner),
     );
     pb.enable_steady_tick(Duration::from_millis(80));
-    pb.set_message(root.display().to_string());
 
     // Build directory tree
-    let tree = build_tree(&root, cli.follow_symlinks, &pb)?;
-    pb.finish_and_clear();
-
-    // Setup terminal
-    enable_raw_mode()?;
-    let mut stdout = std::io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
-
-    // Create app and event handler
-    let mut app = App::new(tree);
-    let events = Events::new(Duration::from_millis(100));
-
-    // Main event loop
-    terminal.clear()?;
-    loop {
-        // Draw UI
-        terminal.draw(|f| draw_ui(f, &app))?;
-
-        // Handle events
-        match events.next()? {
-            event::Event::Input(key) => {
-                if let Some(action) = event::handle_key_event(key.code) {
-                    match action {
-                        Action::Quit => break,
-                        Action::ToggleSort => app.toggle_sort(),
-                        Action::MoveSelection(delta) => app.move_selection(delta),
-                        Action::NavigateIn => {
-                            app.navigate_into();
-                        }
-                        Action::NavigateOut => {
-                            app.navigate_out();
+    let mut size_policy = cli.size_policy.unwrap_or_default();
+    size_policy.dir_overhead |= cli.count_dir_overhead;
+    let options = ScanOptions {
+        follow_symlinks: cli.follow_symlinks,
+        contain_symlinks: cli.follow_symlinks_careful,
+        size_mode: if cli.apparent_size {
+            SizeMode::Apparent
+        } else {
+            SizeMode::Allocated
+        },
+        precise_sparse: cli.precise_sparse,
+        size_policy,
+        include: cli.include.clone(),
+        exclude: cli.exclude.clone(),
+        use_gitignore: cli.use_gitignore,
+        one_file_system: cli.one_file_system,
+        timeout: cli.scan_timeout,
+        max_entries: cli.max_entries,
+        max_depth: cli.max_depth,
+        threads: match cli.threads {
+            Some(0) => None,
+            Some(threads) => Some(threads),
+            None => cli.nice.then(core::priority::nice_threads),
+        },
+        xattrs: cli.xattrs,
+        cluster_size: cli.size_on_disk.then(|| {
+            core::volume::cluster_size(&root).unwrap_or(core::volume::DEFAULT_CLUSTER_SIZE)
+        }),
+        ..ScanOptions::default()
+    };
+    let cached = if cli.reuse_cache && !cli.refresh && !multiple {
+        pb.set_message(format!("Loading the cached scan of {}", root.display()));
+        cache::load(&root, &options)
+    } else {
+        None
+    };
+    let cached_at = cached.as_ref().map(|cached| cached.scanned);
+    // The interface shows the tree while it's read; the other outputs need
+    // all of it first
+    let exporting = cli.output != OutputFormat::Tui || cli.format_template.is_some();
+    let scan = match cached {
+        Some(cached) => {
+            pb.finish_and_clear();
+            Some(cached.scan)
+        }
+        None if !exporting && !multiple => {
+            pb.finish_and_clear();
+            if cli.nice {
+                if let Err(err) = core::priority::lower_priority() {
+                    eprintln!("warning: couldn't lower priority: {}", err);
+                }
+            }
+            None
+        }
+        None => {
+            // The first Ctrl-C stops the walk and shows what was read so
+            // far; a second one, or any after the scan, quits outright
+            let cancel = std::sync::Arc::clone(&options.cancel);
+            ctrlc::set_handler(move || {
+                if cancel.swap(true, Ordering::SeqCst) {
+                    std::process::exit(130);
+                }
+            })?;
+            if cli.nice {
+                if let Err(err) = core::priority::lower_priority() {
+                    pb.println(format!("warning: couldn't lower priority: {}", err));
+                }
+            }
+            let mut scans = Vec::new();
+            for (i, root) in roots.iter().enumerate() {
+                let which = if multiple {
+                    format!("{} ({}/{})", root.display(), i + 1, roots.len())
+                } else {
+                    root.display().to_string()
+                };
+                if multiple && cli.reuse_cache && !cli.refresh {
+                    if let Some(cached) = cache::load(root, &options) {
+                        scans.push(cached.scan);
+                        continue;
+                    }
+                }
+                match cli.scan_timeout {
+                    Some(timeout) => pb.set_message(format!(
+                        "Scanning {} (stopping after {}s, use --scan-timeout to change)",
+                        which,
+                        timeout.as_secs()
+                    )),
+                    None => pb.set_message(format!("Scanning {}", which)),
+                }
+                let scan = build_tree(root, &options, &pb)?;
+
+                // Partial totals would read as the directory shrinking
+                if !scan.completeness.is_partial() {
+                    if let Err(err) = config::record_scan(root, scan.tree.size) {
+                        pb.println(format!("warning: couldn't record scan history: {}", err));
+                    }
+                    if cli.reuse_cache || cli.refresh {
+                        if let Err(err) = cache::save(root, &options, &scan) {
+                            pb.println(format!("warning: couldn't cache the scan: {}", err));
                         }
                     }
                 }
+                scans.push(scan);
+            }
+            options.cancel.store(true, Ordering::SeqCst);
+            pb.finish_and_clear();
+            if multiple {
+                Some(core::combine_scans(&root, scans))
+            } else {
+                scans.pop()
             }
-            _ => {}
         }
+    };
+
+    // Outputs other than the interface have no other place to show errors,
+    // so they go to stderr unless sent elsewhere
+    let errors_to = cli
+        .errors_to
+        .clone()
+        .or_else(|| exporting.then(|| PathBuf::from("-")));
+    if let (Some(dest), Some(scan)) = (&errors_to, &scan) {
+        write_errors_to(dest, &scan.errors)?;
     }
 
-    // Cleanup terminal
-    disable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        LeaveAlternateScreen,
-        DisableMouseCapture
-    )?;
-    terminal.show_cursor()?;
+    let size_format = if cli.blocks {
+        SizeFormat::Blocks(cli.block_size)
+    } else {
+        let units = cli.units.or_else(|| config::load_units(&root));
+        SizeFormat::Human(units.unwrap_or_default())
+    };
+
+    if let Some(scan) = scan.as_ref().filter(|_| exporting) {
+        // The interface's order and filters, as it would open on this tree
+        let viewed = cli.export_as_viewed.then(|| {
+            let mut app = App::new(scan.tree.clone());
+            app.min_size = cli.min_size;
+            app.viewed_tree()
+        });
+        let tree = viewed.as_ref().unwrap_or(&scan.tree);
+        let limit = cli.max_results.map(|max| ResultLimit {
+            max,
+            scope: cli.max_results_scope,
+        });
+        match cli.output {
+            OutputFormat::Tui => {}
+            OutputFormat::Du => {
+                export::write_du(tree, size_format, limit, std::io::stdout().lock())?;
+                return Ok(());
+            }
+            OutputFormat::Csv => {
+                export::write_csv(tree, size_format, cli.max_results, std::io::stdout().lock())?;
+                return Ok(());
+            }
+            OutputFormat::Json => {
+                export::write_json(tree, limit, cli.json_pretty, std::io::stdout().lock())?;
+                return Ok(());
+            }
+            OutputFormat::TreemapJson => {
+                export::write_treemap_json(tree, limit, cli.json_pretty, std::io::stdout().lock())?;
+                return Ok(());
+            }
+        }
+
+        if let Some(template) = &cli.format_template {
+            export::write_template(tree, template, size_format, limit, std::io::stdout().lock())?;
+            return Ok(());
+        }
+    }
+
+    // Create app
+    let streaming = scan.is_none();
+    let mut app = match scan {
+        Some(scan) => {
+            let mut app = App::new(scan.tree);
+            app.completeness = scan.completeness;
+            app.errors = scan.errors;
+            app.scan_times = scan.scan_times;
+            app
+        }
+        None => App::new(TreeBuilder::new(&root, options.max_depth).snapshot()),
+    };
+    app.show_scan_times = cli.scan_times;
+    app.recent = cli.recent;
+    app.warn_at = cli.warn_at;
+    app.min_size = cli.min_size;
+    app.read_only = cli.safe;
+    app.show_avg_size = cli.avg_size;
+    app.show_file_count = cli.file_count;
+    app.show_modified = cli.modified;
+    app.detailed = cli.detailed;
+    app.collapse_chains = cli.collapse_chains;
+    app.show_rounded = cli.size_on_disk;
+    app.glyphs = glyphs;
+    app.theme = Theme::detect(cli.no_color, cli.theme);
+    app.keymap = keymap;
+    if let Some(rate) = cli.tick_rate {
+        app.tick_rate = rate;
+    }
+    // Scans started from the interface get a token of their own
+    app.scan_options = ScanOptions {
+        cancel: Default::default(),
+        ..options
+    };
+    app.size_format = size_format;
+    app.combined_roots = multiple;
+    app.no_canonicalize = cli.no_canonicalize;
+    app.cache_scans = cli.reuse_cache || cli.refresh;
+    app.cached_at = cached_at;
+    if streaming {
+        app.stream_scan(app.cache_scans);
+    }
+    if cli.inodes {
+        app.show_inodes = true;
+        app.free_inodes = core::volume::free_inodes(&root);
+    }
+
+    // Keep stdout clean for the printed path when a shell wrapper captures it
+    let exit_path = if cli.print_path_on_exit {
+        run_tui_on(&mut app, std::io::stderr())?
+    } else {
+        run_tui_on(&mut app, std::io::stdout())?
+    };
+    // With no clipboard to copy to; stderr keeps a captured stdout to the
+    // one path a shell wrapper expects
+    for path in &app.uncopied {
+        if cli.print_path_on_exit {
+            eprintln!("{}", path.display());
+        } else {
+            println!("{}", path.display());
+        }
+    }
+    if let Some(dest) = cli.errors_to.as_ref().filter(|_| streaming) {
+        write_errors_to(dest, &app.errors)?;
+    }
+    // Ctrl-C picks nothing, so a wrapper's `cd` stays put
+    if let Some(path) = exit_path.filter(|_| cli.print_path_on_exit) {
+        println!("{}", path.display());
+    }
+    Ok(())
+}
+
+/// The roots as absolute paths, or canonical ones unless `no_canonicalize`
+/// is set. Roots that can't be scanned, or that another root already
+/// covers, are reported and left out.
+fn resolve_roots(inputs: &[PathBuf], no_canonicalize: bool) -> Vec<PathBuf> {
+    let mut roots: Vec<PathBuf> = Vec::new();
+    for input in inputs {
+        let resolved = if no_canonicalize {
+            std::path::absolute(input)
+        } else {
+            input.canonicalize()
+        };
+        let root = match resolved {
+            Ok(root) if root.is_dir() => root,
+            Ok(_) => {
+                eprintln!("warning: skipping {}: not a directory", input.display());
+                continue;
+            }
+            Err(err) => {
+                eprintln!("warning: skipping {}: {}", input.display(), err);
+                continue;
+            }
+        };
+        // Scanning a root twice would count it twice
+        if let Some(outer) = roots.iter().find(|r| root.starts_with(r)) {
+            eprintln!(
+                "warning: skipping {}: already scanned as part of {}",
+                input.display(),
+                outer.display()
+            );
+            continue;
+        }
+        roots.retain(|r| {
+            let inside = r.starts_with(&root);
+            if inside {
+                eprintln!(
+                    "warning: skipping {}: already scanned as part of {}",
+                    r.display(),
+                    root.display()
+                );
+            }
+            !inside
+        });
+        roots.push(root);
+    }
+    roots
+}
+
+/// The deepest directory holding every one of `paths`; the path itself
+/// when there's one.
+fn common_ancestor(paths: &[PathBuf]) -> PathBuf {
+    let mut common = paths[0].clone();
+    for path in &paths[1..] {
+        while !path.starts_with(&common) && common.pop() {}
+    }
+    common
+}
+
+/// Writes `errors` as JSON lines to `dest`, or to stderr for `-`.
+fn write_errors_to(dest: &Path, errors: &[ScanError]) -> Result<()> {
+    if dest.as_os_str() == "-" {
+        export::write_errors(errors, std::io::stderr().lock())?;
+    } else {
+        export::write_errors(errors, std::fs::File::create(dest)?)?;
+    }
     Ok(())
 }
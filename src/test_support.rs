@@ -0,0 +1,69 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::core::DirEntryInfo;
+
+/// A scratch directory under the system temp dir, removed when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("dua-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Writes `len` bytes to `name` inside the directory and returns its path.
+    pub fn file(&self, name: &str, len: usize) -> PathBuf {
+        let path = self.0.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(&path, vec![b'x'; len]).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A file entry of `size` bytes.
+pub fn file_entry(path: &str, size: u64) -> DirEntryInfo {
+    DirEntryInfo {
+        path: PathBuf::from(path),
+        size,
+        allocated_size: size,
+        rounded_size: size,
+        is_dir: false,
+        inodes: 1,
+        file_count: 1,
+        modified: None,
+        newest_modified: None,
+        mount_point: false,
+        hardlink: None,
+        symlink_loop: false,
+        children: Vec::new(),
+    }
+}
+
+/// A directory entry holding `children`, with its totals summed from them.
+pub fn dir_entry(path: &str, children: Vec<DirEntryInfo>) -> DirEntryInfo {
+    DirEntryInfo {
+        size: children.iter().map(|c| c.size).sum(),
+        allocated_size: children.iter().map(|c| c.allocated_size).sum(),
+        rounded_size: children.iter().map(|c| c.rounded_size).sum(),
+        is_dir: true,
+        inodes: 1 + children.iter().map(|c| c.inodes).sum::<u64>(),
+        file_count: children.iter().map(|c| c.file_count).sum(),
+        children,
+        ..file_entry(path, 0)
+    }
+}
@@ -1,14 +1,291 @@
-use crate::core::DirEntryInfo;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
 
-#[derive(PartialEq, Clone, Copy)]
-pub enum SortBy {
+use indicatif::ProgressBar;
+use tui::layout::Rect;
+use tui::widgets::{ListState, TableState};
+
+use crate::{
+    cache, config,
+    core::{
+        build_tree,
+        delete::{remove_tree, DeleteOutcome, DeleteUpdate},
+        restat, scan_result,
+        sort::natural_cmp,
+        stream_tree,
+        units::{SizeFormat, UnitSystem},
+        volume,
+        walk::WalkOutcome,
+        Completeness, DirEntryInfo, Hardlink, ScanError, ScanOptions, ScanResult, ScanUpdate,
+        TreeBuilder,
+    },
+    integration::{self, clipboard::Clipboard},
+    ui::{
+        event,
+        glyphs::{self, Glyphs},
+        keymap::Keymap,
+        theme::Theme,
+    },
+};
+
+#[derive(PartialEq, Clone, Copy, Default)]
+pub enum SortKey {
     Name,
+    #[default]
     Size,
+    AvgSize,
+    /// Number of files below an entry.
+    Files,
+    /// Newest modification anywhere below a directory.
+    Modified,
+}
+
+impl SortKey {
+    pub fn name(self) -> &'static str {
+        match self {
+            SortKey::Name => "name",
+            SortKey::Size => "size",
+            SortKey::AvgSize => "average file size",
+            SortKey::Files => "file count",
+            SortKey::Modified => "modified",
+        }
+    }
+
+    /// Names read best A to Z, sizes and counts largest first, times newest
+    /// first.
+    fn ascending_by_default(self) -> bool {
+        self == SortKey::Name
+    }
+}
+
+/// How the listing is ordered: by which figure, and which way.
+#[derive(PartialEq, Clone, Copy)]
+pub struct SortBy {
+    pub key: SortKey,
+    pub ascending: bool,
 }
 
 impl Default for SortBy {
     fn default() -> Self {
-        SortBy::Size
+        SortBy::new(SortKey::default())
+    }
+}
+
+impl SortBy {
+    /// Sorts by `key` in its usual direction.
+    pub fn new(key: SortKey) -> Self {
+        SortBy {
+            key,
+            ascending: key.ascending_by_default(),
+        }
+    }
+
+    pub fn compare(self, a: &DirEntryInfo, b: &DirEntryInfo) -> Ordering {
+        let ascending = match self.key {
+            SortKey::Name => {
+                let name = |e: &DirEntryInfo| {
+                    e.path
+                        .file_name()
+                        .map(|n| n.to_string_lossy().into_owned())
+                        .unwrap_or_default()
+                };
+                natural_cmp(&name(a), &name(b))
+            }
+            SortKey::Size => a.size.cmp(&b.size),
+            SortKey::AvgSize => a.average_file_size().cmp(&b.average_file_size()),
+            SortKey::Files => a.file_count.cmp(&b.file_count),
+            SortKey::Modified => a.newest_modified.cmp(&b.newest_modified),
+        };
+        if self.ascending {
+            ascending
+        } else {
+            ascending.reverse()
+        }
+    }
+}
+
+/// What a line typed into the status bar prompt is for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PromptKind {
+    GoTo,
+    Open,
+    /// Narrows the listing as the query is typed.
+    Filter,
+}
+
+impl PromptKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            PromptKind::GoTo => "Go to: ",
+            PromptKind::Open => "Open new root: ",
+            PromptKind::Filter => "Filter: ",
+        }
+    }
+}
+
+pub struct Prompt {
+    pub kind: PromptKind,
+    pub input: String,
+}
+
+/// A flat list of matching entries from the scan, shown in place
+/// of the directory listing.
+pub struct FlatView {
+    pub title: &'static str,
+    /// Entry paths are shown relative to this.
+    pub base: PathBuf,
+    pub entries: Vec<DirEntryInfo>,
+    /// Extra text shown after an entry's label.
+    pub notes: HashMap<PathBuf, String>,
+    pub selected: usize,
+}
+
+/// Where the last frame drew the listing's rows, so mouse clicks can be
+/// mapped back to entries.
+#[derive(Debug, Clone, Default)]
+pub struct ListLayout {
+    /// Screen area holding the rows, below any header.
+    pub area: Rect,
+    /// Lines per row: two in the detailed list.
+    pub row_height: u16,
+    /// Index of the first row on screen.
+    pub offset: usize,
+    /// The table's and the detailed list's scroll positions, each kept
+    /// while the other is shown.
+    pub table_offset: usize,
+    pub list_offset: usize,
+    /// What each row selects: a child index in the listing (inline
+    /// grandchildren select their parent), an entry index in a flat view,
+    /// nothing for a summary row.
+    pub targets: Vec<Option<usize>>,
+}
+
+/// A scan running in the background: of another root, whose tree replaces
+/// the current one when done, or of a directory in the tree, spliced back
+/// in at its place.
+pub struct PendingScan {
+    pub root: PathBuf,
+    /// Hidden bar the scan reports its phase to, shown in the status bar.
+    pub progress: ProgressBar,
+    pub started: Instant,
+    /// Rescanning part of the current tree rather than opening a new one.
+    in_place: bool,
+    handle: JoinHandle<std::io::Result<ScanResult>>,
+}
+
+/// The scan of the root the interface started on, while it's still
+/// running; the tree read so far is shown and fills in as entries arrive.
+pub struct LiveScan {
+    pub root: PathBuf,
+    /// Files and bytes read so far.
+    pub files: u64,
+    pub bytes: u64,
+    updates: Receiver<ScanUpdate>,
+    builder: TreeBuilder,
+    options: ScanOptions,
+    /// Entries arrived since the shown tree was last rebuilt.
+    stale: bool,
+    next_rebuild: Instant,
+    /// Save the finished scan for `--reuse-cache`.
+    cache: bool,
+}
+
+impl Drop for LiveScan {
+    fn drop(&mut self) {
+        // Nobody is left to show the rest of the walk to
+        self.options.cancel.store(true, AtomicOrdering::Relaxed);
+    }
+}
+
+/// A delete running in the background. The tree loses each entry as it's
+/// removed from disk, so the totals above it drop as it goes.
+pub struct PendingDelete {
+    pub path: PathBuf,
+    /// Entries removed so far, out of the `total` the tree has for it.
+    pub removed: u64,
+    pub total: u64,
+    /// Bytes the removed entries held, leaving out files that still have a
+    /// name elsewhere, which free nothing.
+    pub freed: u64,
+    /// Removed files whose data lives on under another name.
+    pub still_linked: u64,
+    updates: Receiver<DeleteUpdate>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl Drop for PendingDelete {
+    fn drop(&mut self) {
+        // Quitting mid-delete leaves the rest on disk
+        self.cancel.store(true, AtomicOrdering::Relaxed);
+    }
+}
+
+/// A rebuild of the live tree is followed by a wait this many times as long
+/// as it took, so a big tree doesn't spend the scan redrawing itself.
+const REBUILD_SPACING: u32 = 4;
+
+/// With `App::rollup_small` set, children under this share of their
+/// directory are listed as one row.
+const ROLLUP_SHARE: f64 = 0.01;
+
+/// Entries listed together as one summary row.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rollup {
+    pub count: usize,
+    pub size: u64,
+}
+
+/// The entries of `children` under `threshold` bytes, which are listed as
+/// one row instead of each on their own.
+pub fn rollup<'a>(children: impl IntoIterator<Item = &'a DirEntryInfo>, threshold: u64) -> Rollup {
+    children
+        .into_iter()
+        .filter(|c| c.size < threshold)
+        .fold(Rollup::default(), |total, c| Rollup {
+            count: total.count + 1,
+            size: total.size + c.size,
+        })
+}
+
+/// Rows in the largest-directories view.
+const LARGEST_DIRS: usize = 100;
+
+/// Rows in the largest-files view.
+const LARGEST_FILES: usize = 100;
+
+/// A file ranked by size, ties broken by path, for the bounded heap in
+/// `App::top_files`.
+struct BySize<'a>(&'a DirEntryInfo);
+
+impl BySize<'_> {
+    fn key(&self) -> (u64, Reverse<&Path>) {
+        (self.0.size, Reverse(&self.0.path))
+    }
+}
+
+impl PartialEq for BySize<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for BySize<'_> {}
+
+impl PartialOrd for BySize<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BySize<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
     }
 }
 
@@ -17,6 +294,93 @@ pub struct App {
     pub stack: Vec<DirEntryInfo>,
     pub sort_by: SortBy,
     pub selected: usize,
+    /// Kept across frames so the table remembers its scroll offset.
+    pub table_state: TableState,
+    /// Scroll state for the two-line list used in detailed mode.
+    pub list_state: ListState,
+    /// Row placement from the last frame.
+    pub list_layout: ListLayout,
+    /// Show each entry on two lines so long names aren't cut off.
+    pub detailed: bool,
+    /// Why the totals might be short of what's on disk.
+    pub completeness: Completeness,
+    /// Show the breakdown of `completeness` over the listing.
+    pub show_completeness: bool,
+    /// Show the treemap panel beside the listing.
+    pub show_treemap: bool,
+    /// Show the key bindings over the listing until the next key press.
+    pub show_help: bool,
+    /// Entries the scan couldn't read.
+    pub errors: Vec<ScanError>,
+    /// How long each of the root's children took to scan.
+    pub scan_times: HashMap<PathBuf, Duration>,
+    pub show_scan_times: bool,
+    pub show_inodes: bool,
+    pub show_avg_size: bool,
+    pub show_file_count: bool,
+    pub show_modified: bool,
+    /// Show sizes rounded to the volume's allocation unit next to "Size".
+    pub show_rounded: bool,
+    /// Free inodes on the scanned volume, when `show_inodes` is set.
+    pub free_inodes: Option<u64>,
+    /// Files modified within this window are highlighted.
+    pub recent: Option<Duration>,
+    /// One-off notice shown in the status bar until the next key press.
+    pub message: Option<String>,
+    /// Show `message` as a warning rather than a plain notice.
+    pub alert: bool,
+    /// Size directories by the files directly inside them instead of their
+    /// whole subtree.
+    pub direct_sizes: bool,
+    /// Show a run of single-child directories as one row.
+    pub collapse_chains: bool,
+    /// Refuse every action that changes files on disk.
+    pub read_only: bool,
+    /// Directories larger than this are drawn as warnings.
+    pub warn_at: Option<u64>,
+    /// Directories whose children are previewed inline under their row.
+    pub expanded: HashSet<PathBuf>,
+    pub size_format: SizeFormat,
+    pub glyphs: Glyphs,
+    pub theme: Theme,
+    pub keymap: Keymap,
+    /// How often the screen is redrawn while no input arrives, so progress
+    /// and other changes show up by themselves.
+    pub tick_rate: Duration,
+    /// Pending line input; keys go to it instead of the bindings while set.
+    pub prompt: Option<Prompt>,
+    /// Only children whose name contains this, ignoring case, are listed.
+    pub filter: Option<String>,
+    /// Children smaller than this are left out of the listing.
+    pub min_size: Option<u64>,
+    /// List what the name filter and minimum size hide instead of what they
+    /// let through, while either is set.
+    pub invert_filter: bool,
+    /// List the children under `ROLLUP_SHARE` of the directory as one row,
+    /// so a crowd of small ones doesn't bury the big ones.
+    pub rollup_small: bool,
+    /// Opened on the first copy and kept, since on some desktops the copied
+    /// text lives only as long as the connection.
+    pub clipboard: Option<Clipboard>,
+    /// Paths that couldn't be copied, to print once the interface closes.
+    pub uncopied: Vec<PathBuf>,
+    /// Entry waiting for a `y` before it's deleted; any other key cancels.
+    pub confirm_delete: Option<PathBuf>,
+    pub flat_view: Option<FlatView>,
+    /// How the tree was scanned, so refreshed sizes are counted the same way.
+    pub scan_options: ScanOptions,
+    pub pending_scan: Option<PendingScan>,
+    pub live_scan: Option<LiveScan>,
+    pub pending_delete: Option<PendingDelete>,
+    /// When the tree shown was read from the cache, the time of that scan.
+    pub cached_at: Option<SystemTime>,
+    /// Save finished scans of the root for `--reuse-cache`.
+    pub cache_scans: bool,
+    /// The top of the tree only holds several scanned roots side by side
+    /// and wasn't scanned itself.
+    pub combined_roots: bool,
+    /// Open new roots as typed instead of resolving them through symlinks.
+    pub no_canonicalize: bool,
 }
 
 impl App {
@@ -26,68 +390,2016 @@ impl App {
             stack: vec![root],
             sort_by: SortBy::default(),
             selected: 0,
+            table_state: TableState::default(),
+            list_state: ListState::default(),
+            list_layout: ListLayout::default(),
+            detailed: false,
+            completeness: Completeness::default(),
+            show_completeness: false,
+            show_treemap: false,
+            show_help: false,
+            errors: Vec::new(),
+            scan_times: HashMap::new(),
+            show_scan_times: false,
+            show_inodes: false,
+            show_avg_size: false,
+            show_file_count: false,
+            show_modified: false,
+            show_rounded: false,
+            free_inodes: None,
+            recent: None,
+            message: None,
+            alert: false,
+            direct_sizes: false,
+            collapse_chains: false,
+            read_only: false,
+            warn_at: None,
+            expanded: HashSet::new(),
+            size_format: SizeFormat::default(),
+            glyphs: glyphs::UNICODE,
+            theme: Theme::default(),
+            keymap: Keymap::default(),
+            tick_rate: event::DEFAULT_TICK_RATE,
+            prompt: None,
+            filter: None,
+            min_size: None,
+            invert_filter: false,
+            rollup_small: false,
+            clipboard: None,
+            uncopied: Vec::new(),
+            confirm_delete: None,
+            flat_view: None,
+            scan_options: ScanOptions::default(),
+            pending_scan: None,
+            live_scan: None,
+            pending_delete: None,
+            cached_at: None,
+            cache_scans: false,
+            combined_roots: false,
+            no_canonicalize: false,
+        }
+    }
+
+    pub fn format_size(&self, bytes: u64) -> String {
+        self.size_format.format(bytes)
+    }
+
+    /// Switches between decimal and binary units, returning the new choice,
+    /// or `None` when sizes are shown as block counts.
+    pub fn toggle_units(&mut self) -> Option<UnitSystem> {
+        match self.size_format {
+            SizeFormat::Human(units) => {
+                self.size_format = SizeFormat::Human(units.toggled());
+                Some(units.toggled())
+            }
+            SizeFormat::Blocks(_) => {
+                self.message = Some("Units don't apply to block counts".to_string());
+                None
+            }
+        }
+    }
+
+    /// The average-file-size column is shown on request or while sorting by it.
+    pub fn shows_avg_size(&self) -> bool {
+        self.show_avg_size || self.sort_by.key == SortKey::AvgSize
+    }
+
+    /// Likewise for the file-count column.
+    pub fn shows_file_count(&self) -> bool {
+        self.show_file_count || self.sort_by.key == SortKey::Files
+    }
+
+    /// Likewise for the modification-time column.
+    pub fn shows_modified(&self) -> bool {
+        self.show_modified || self.sort_by.key == SortKey::Modified
+    }
+
+    pub fn is_recent(&self, entry: &DirEntryInfo) -> bool {
+        match (self.recent, entry.modified) {
+            (Some(window), Some(modified)) => SystemTime::now()
+                .duration_since(modified)
+                .map_or(true, |age| age < window),
+            _ => false,
+        }
+    }
+
+    /// The highlighted entry, in the flat view if one is open.
+    pub fn selected_entry(&self) -> Option<&DirEntryInfo> {
+        match &self.flat_view {
+            Some(view) => view.entries.get(view.selected),
+            None => self.selected_child(),
+        }
+    }
+
+    /// The highlighted child of the current directory, unless the filter
+    /// hides it.
+    fn selected_child(&self) -> Option<&DirEntryInfo> {
+        self.current_node
+            .children
+            .get(self.selected)
+            .filter(|c| self.matches_filter(c))
+    }
+
+    /// Whether `entry` gets a row of its own: it's at least the minimum
+    /// size and its name contains the filter query, ignoring case (or,
+    /// inverted, it fails one of those), and it isn't rolled up.
+    pub fn matches_filter(&self, entry: &DirEntryInfo) -> bool {
+        self.passes_filters(entry) && !self.is_rolled_up(entry)
+    }
+
+    /// The name filter and minimum size, turned around when inverted.
+    fn passes_filters(&self, entry: &DirEntryInfo) -> bool {
+        let passes = self.is_big_enough(entry) && self.matches_name(entry);
+        passes != self.filter_inverted()
+    }
+
+    /// Whether the listing shows what the filters hide; only while one is set.
+    pub fn filter_inverted(&self) -> bool {
+        self.invert_filter && (self.filter.is_some() || self.min_size.is_some())
+    }
+
+    /// The whole tree as the listing would show it: every level in the
+    /// current sort order, leaving out what the name filter and minimum size
+    /// hide (or, inverted, let through). Totals stay as scanned. Rolled-up
+    /// entries are kept, as they're only drawn together.
+    pub fn viewed_tree(&self) -> DirEntryInfo {
+        fn view(app: &App, entry: &DirEntryInfo) -> DirEntryInfo {
+            let mut children: Vec<DirEntryInfo> = entry
+                .children
+                .iter()
+                .filter(|c| app.passes_filters(c))
+                .map(|c| view(app, c))
+                .collect();
+            children.sort_by(|a, b| app.sort_by.compare(a, b));
+            DirEntryInfo {
+                children,
+                ..entry.without_children()
+            }
+        }
+        view(self, &self.stack[0])
+    }
+
+    /// Swaps the listing between what the name filter and minimum size let
+    /// through and what they hide.
+    pub fn toggle_invert_filter(&mut self) {
+        if self.filter.is_none() && self.min_size.is_none() {
+            self.message = Some("No filter or minimum size to invert".to_string());
+            return;
+        }
+        self.invert_filter = !self.invert_filter;
+        self.message = Some(if self.invert_filter {
+            "Showing only what the filters hide".to_string()
+        } else {
+            "Showing what the filters let through".to_string()
+        });
+        self.select_visible();
+    }
+
+    fn is_big_enough(&self, entry: &DirEntryInfo) -> bool {
+        self.min_size.is_none_or(|min| entry.size >= min)
+    }
+
+    /// Size under which children of the current directory are rolled up,
+    /// when that's on.
+    fn rollup_threshold(&self) -> Option<u64> {
+        self.rollup_small
+            .then_some((self.current_node.size as f64 * ROLLUP_SHARE) as u64)
+    }
+
+    fn is_rolled_up(&self, entry: &DirEntryInfo) -> bool {
+        self.rollup_threshold()
+            .is_some_and(|threshold| entry.size < threshold)
+    }
+
+    /// The children of the current directory listed together as one row,
+    /// out of those the name filter and minimum size let through (or hide,
+    /// inverted).
+    pub fn rolled_up(&self) -> Rollup {
+        let Some(threshold) = self.rollup_threshold() else {
+            return Rollup::default();
+        };
+        let children = self
+            .current_node
+            .children
+            .iter()
+            .filter(|c| self.passes_filters(c));
+        rollup(children, threshold)
+    }
+
+    /// Turns rolling up small children into one row on or off.
+    pub fn toggle_rollup(&mut self) {
+        self.rollup_small = !self.rollup_small;
+        self.message = Some(if self.rollup_small {
+            "Listing entries under 1% of their directory as one row".to_string()
+        } else {
+            "Listing every entry on its own".to_string()
+        });
+        self.select_visible();
+    }
+
+    fn matches_name(&self, entry: &DirEntryInfo) -> bool {
+        let Some(query) = &self.filter else {
+            return true;
+        };
+        entry.path.file_name().is_some_and(|name| {
+            name.to_string_lossy()
+                .to_lowercase()
+                .contains(&query.to_lowercase())
+        })
+    }
+
+    /// Children that match the name filter but fall under the minimum size;
+    /// none while inverted, as those are what's listed.
+    pub fn hidden_by_size(&self) -> usize {
+        if self.filter_inverted() {
+            return 0;
+        }
+        self.current_node
+            .children
+            .iter()
+            .filter(|c| self.matches_name(c) && !self.is_big_enough(c))
+            .count()
+    }
+
+    /// Steps the minimum size through a few thresholds and back to none,
+    /// starting above the current one.
+    pub fn cycle_min_size(&mut self) {
+        let unit: u64 = match self.size_format {
+            SizeFormat::Human(UnitSystem::Binary) => 1024,
+            _ => 1000,
+        };
+        let mega = unit * unit;
+        let steps = [mega, 10 * mega, 100 * mega, unit * mega];
+        self.min_size = steps
+            .into_iter()
+            .find(|&step| self.min_size.is_none_or(|min| step > min));
+        self.message = Some(match self.min_size {
+            Some(min) => format!("Hiding entries under {}", self.format_size(min)),
+            None => "Showing entries of every size".to_string(),
+        });
+        self.select_visible();
+    }
+
+    /// Indexes of the children the filter lets through.
+    pub fn visible_children(&self) -> Vec<usize> {
+        (0..self.current_node.children.len())
+            .filter(|&i| self.matches_filter(&self.current_node.children[i]))
+            .collect()
+    }
+
+    /// Narrows the listing to names containing `query` (`None` or empty
+    /// shows everything), moving the selection to the first match if the
+    /// selected entry is filtered out.
+    pub fn set_filter(&mut self, query: Option<String>) {
+        self.filter = query.filter(|q| !q.is_empty());
+        self.select_visible();
+    }
+
+    /// Moves the selection to the first listed child if the filter hides
+    /// the selected one.
+    fn select_visible(&mut self) {
+        if self.selected_child().is_none() {
+            if let Some(&first) = self.visible_children().first() {
+                self.selected = first;
+            }
         }
     }
 
     pub fn navigate_into(&mut self) -> bool {
-        if let Some(selected_entry) = self.current_node.children.get(self.selected) {
-            if selected_entry.is_dir && !selected_entry.children.is_empty() {
-                let new_node = selected_entry.clone();
-                self.stack.push(new_node.clone());
-                self.current_node = new_node;
+        if let Some(view) = self.flat_view.take() {
+            // Show the picked entry in its directory
+            if let Some(entry) = view.entries.get(view.selected) {
+                let path = entry.path.display().to_string();
+                self.go_to(&path);
+            }
+            return true;
+        }
+        if let Some(selected_entry) = self.selected_child() {
+            if selected_entry.is_dir {
+                // Step through a collapsed chain one level at a time, so the
+                // stack still mirrors the path
+                let chain: Vec<DirEntryInfo> =
+                    self.chain(selected_entry).into_iter().cloned().collect();
+                self.stack.extend(chain);
+                self.current_node = self.stack.last().unwrap().clone();
+                self.sort_children();
                 self.selected = 0;
+                self.filter = None;
+                self.select_visible();
                 return true;
             }
         }
         false
     }
 
+    /// The directories `entry`'s row stands for: itself, followed, when
+    /// chains are collapsed, by each directory that is the only child of
+    /// the one before.
+    pub fn chain<'a>(&self, entry: &'a DirEntryInfo) -> Vec<&'a DirEntryInfo> {
+        let mut chain = vec![entry];
+        if !self.collapse_chains {
+            return chain;
+        }
+        let mut last = entry;
+        while let [only] = last.children.as_slice() {
+            if !only.is_dir {
+                break;
+            }
+            chain.push(only);
+            last = only;
+        }
+        chain
+    }
+
     pub fn navigate_out(&mut self) -> bool {
+        if self.flat_view.take().is_some() {
+            return true;
+        }
         if self.stack.len() > 1 {
-            self.stack.pop();
-            if let Some(prev_node) = self.stack.last() {
-                self.current_node = prev_node.clone();
-                // Try to maintain selection position when going back
-                if let Some(pos) = self
-                    .current_node
-                    .children
-                    .iter()
-                    .position(|c| c.path == self.current_node.path)
-                {
-                    self.selected = pos.min(self.current_node.children.len().saturating_sub(1));
-                }
-                return true;
+            let came_from = self.stack.pop().unwrap().path;
+            // Skip back over a collapsed chain, whose directories have no
+            // listing of their own
+            while self.collapse_chains
+                && self.stack.len() > 1
+                && self.chain(self.stack.last().unwrap()).len() > 1
+            {
+                self.stack.pop();
             }
+            self.current_node = self.stack.last().unwrap().clone();
+            self.filter = None;
+            // Select the row we left through, the start of its chain if any
+            self.selected = self
+                .current_node
+                .children
+                .iter()
+                .position(|c| came_from.starts_with(&c.path))
+                .unwrap_or(0);
+            // The sort may have changed since this level was listed
+            self.sort_children();
+            self.select_visible();
+            return true;
         }
         false
     }
 
+    /// Moves the selection by `delta` rows, wrapping around at either end.
     pub fn move_selection(&mut self, delta: isize) {
-        if self.current_node.children.is_empty() {
+        self.step_selection(delta, true);
+    }
+
+    /// Moves the selection by `pages` screenfuls, stopping at either end.
+    pub fn move_page(&mut self, pages: isize) {
+        let layout = &self.list_layout;
+        let page = (layout.area.height / layout.row_height.max(1)).max(1);
+        self.step_selection(pages.saturating_mul(page as isize), false);
+    }
+
+    pub fn select_first(&mut self) {
+        self.step_selection(isize::MIN, false);
+    }
+
+    pub fn select_last(&mut self) {
+        self.step_selection(isize::MAX, false);
+    }
+
+    fn step_selection(&mut self, delta: isize, wrap: bool) {
+        let step = |pos: usize, len: usize| {
+            if wrap {
+                (pos as isize + delta).rem_euclid(len as isize) as usize
+            } else {
+                (pos as isize)
+                    .saturating_add(delta)
+                    .clamp(0, len as isize - 1) as usize
+            }
+        };
+        if let Some(view) = &mut self.flat_view {
+            let len = view.entries.len();
+            if len > 0 {
+                view.selected = step(view.selected, len);
+            }
+            return;
+        }
+        // Step through the rows the filter leaves
+        let visible = self.visible_children();
+        if visible.is_empty() {
             return;
         }
-        let len = self.current_node.children.len() as isize;
-        self.selected = (self.selected as isize + delta).rem_euclid(len) as usize;
+        let pos = visible
+            .iter()
+            .position(|&i| i == self.selected)
+            .unwrap_or(0);
+        self.selected = visible[step(pos, visible.len())];
     }
 
-    pub fn toggle_sort(&mut self) {
-        self.sort_by = match self.sort_by {
-            SortBy::Name => SortBy::Size,
-            SortBy::Size => SortBy::Name,
+    /// Selects the row at screen position (`column`, `row`), or opens it if
+    /// it was already selected.
+    pub fn click(&mut self, column: u16, row: u16) {
+        let layout = &self.list_layout;
+        let area = layout.area;
+        if layout.row_height == 0
+            || column < area.x
+            || column >= area.x + area.width
+            || row < area.y
+            || row >= area.y + area.height
+        {
+            return;
+        }
+        let index = layout.offset + ((row - area.y) / layout.row_height) as usize;
+        let Some(&Some(target)) = layout.targets.get(index) else {
+            return;
+        };
+        let selected = match &mut self.flat_view {
+            Some(view) => std::mem::replace(&mut view.selected, target),
+            None => std::mem::replace(&mut self.selected, target),
         };
+        if selected == target {
+            self.navigate_into();
+        }
+    }
+
+    pub fn toggle_sort(&mut self) {
+        self.sort_by = SortBy::new(match self.sort_by.key {
+            SortKey::Name => SortKey::Size,
+            SortKey::Size => SortKey::AvgSize,
+            SortKey::AvgSize => SortKey::Files,
+            SortKey::Files => SortKey::Modified,
+            SortKey::Modified => SortKey::Name,
+        });
+        self.sort_children();
+    }
+
+    /// Flips the listing between ascending and descending, keeping the key.
+    pub fn toggle_sort_direction(&mut self) {
+        self.sort_by.ascending = !self.sort_by.ascending;
         self.sort_children();
     }
 
+    /// Re-sorts the listing, keeping the selection on the same entry. The
+    /// table scrolls to keep it visible on the next draw.
     pub fn sort_children(&mut self) {
-        match self.sort_by {
-            SortBy::Name => self
-                .current_node
+        let selected = self
+            .current_node
+            .children
+            .get(self.selected)
+            .map(|e| e.path.clone());
+        let sort_by = self.sort_by;
+        self.current_node
+            .children
+            .sort_by(|a, b| sort_by.compare(a, b));
+        if let Some(pos) = selected.and_then(|path| {
+            self.current_node
                 .children
-                .sort_by(|a, b| a.path.file_name().cmp(&b.path.file_name())),
-            SortBy::Size => self
+                .iter()
+                .position(|c| c.path == path)
+        }) {
+            self.selected = pos;
+        }
+    }
+
+    /// Shows or hides the selected directory's children inline.
+    pub fn toggle_expanded(&mut self) {
+        if self.flat_view.is_some() {
+            return;
+        }
+        let Some(path) = self
+            .selected_child()
+            .filter(|e| e.is_dir)
+            .map(|e| e.path.clone())
+        else {
+            return;
+        };
+        if !self.expanded.remove(&path) {
+            self.expanded.insert(path);
+        }
+    }
+
+    /// Children of `entry` to peek at inline, in the current sort order, or
+    /// `None` if it isn't expanded.
+    pub fn expanded_children<'a>(&self, entry: &'a DirEntryInfo) -> Option<Vec<&'a DirEntryInfo>> {
+        if !self.expanded.contains(&entry.path) {
+            return None;
+        }
+        let mut children: Vec<&DirEntryInfo> = entry.children.iter().collect();
+        children.sort_by(|a, b| self.sort_by.compare(a, b));
+        Some(children)
+    }
+
+    /// Lists every empty file under the current directory, or goes back to
+    /// the listing if that view is already open.
+    pub fn toggle_zero_byte_view(&mut self) {
+        let node = &self.current_node;
+        let entries = node
+            .find_all(&|e| !e.is_dir && e.size == 0)
+            .into_iter()
+            .cloned()
+            .collect();
+        self.toggle_flat_view("Zero-byte files", node.path.clone(), entries);
+    }
+
+    /// Lists the files under the current directory that have several hard
+    /// links, with the names of each file next to each other.
+    pub fn toggle_hardlink_view(&mut self) {
+        let node = &self.current_node;
+        let mut entries: Vec<DirEntryInfo> = node
+            .find_all(&|e| e.hardlink.is_some())
+            .into_iter()
+            .cloned()
+            .collect();
+        // Names of a file together, the one carrying its size first
+        let identity = |e: &DirEntryInfo| e.hardlink.map(|l| (l.device, l.inode));
+        entries.sort_by(|a, b| identity(a).cmp(&identity(b)).then(a.path.cmp(&b.path)));
+        self.toggle_flat_view("Hardlinked files", node.path.clone(), entries);
+    }
+
+    /// Lists the largest directories anywhere in the scan, with full paths.
+    pub fn toggle_largest_dirs_view(&mut self) {
+        let mut dirs = self.stack[0].find_all(&|e| e.is_dir);
+        dirs.sort_by_key(|d| Reverse(d.size));
+        dirs.truncate(LARGEST_DIRS);
+        // The rows only show totals, so leave the subtrees behind
+        let entries = dirs
+            .into_iter()
+            .map(DirEntryInfo::without_children)
+            .collect();
+        self.toggle_flat_view("Largest directories", PathBuf::new(), entries);
+    }
+
+    /// The `n` largest files anywhere in the scan, largest first. Only `n`
+    /// are held at a time, however many files there are.
+    pub fn top_files(&self, n: usize) -> Vec<&DirEntryInfo> {
+        // The smallest kept file sits on top, ready to make room
+        let mut kept = BinaryHeap::with_capacity(n + 1);
+        let mut pending = vec![&self.stack[0]];
+        while let Some(node) = pending.pop() {
+            for child in &node.children {
+                if child.is_dir {
+                    pending.push(child);
+                    continue;
+                }
+                kept.push(Reverse(BySize(child)));
+                if kept.len() > n {
+                    kept.pop();
+                }
+            }
+        }
+        kept.into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(file)| file.0)
+            .collect()
+    }
+
+    /// Lists the largest files anywhere in the scan, with full paths.
+    pub fn toggle_largest_files_view(&mut self) {
+        let entries = self.top_files(LARGEST_FILES).into_iter().cloned().collect();
+        self.toggle_flat_view("Largest files", PathBuf::new(), entries);
+    }
+
+    /// Lists every path the scan couldn't read with the reason, with full
+    /// paths.
+    pub fn toggle_errors_view(&mut self) {
+        let open = self
+            .flat_view
+            .as_ref()
+            .is_some_and(|view| view.title == "Scan errors");
+        if self.errors.is_empty() && !open {
+            self.message = Some("Every entry was read".to_string());
+            return;
+        }
+        let root = &self.stack[0];
+        let entries = self
+            .errors
+            .iter()
+            .map(|err| match root.find(&err.path) {
+                Some(entry) => entry.without_children(),
+                // Unreadable files never made it into the tree
+                None => DirEntryInfo {
+                    path: err.path.clone(),
+                    size: 0,
+                    allocated_size: 0,
+                    rounded_size: 0,
+                    is_dir: false,
+                    inodes: 0,
+                    file_count: 0,
+                    modified: None,
+                    newest_modified: None,
+                    mount_point: false,
+                    hardlink: None,
+                    symlink_loop: false,
+                    children: Vec::new(),
+                },
+            })
+            .collect();
+        let notes = self
+            .errors
+            .iter()
+            .map(|err| (err.path.clone(), format!("{:?}: {}", err.kind, err.message)))
+            .collect();
+        self.toggle_flat_view("Scan errors", PathBuf::new(), entries);
+        if let Some(view) = &mut self.flat_view {
+            view.notes = notes;
+        }
+    }
+
+    /// Opens the flat view `title` with `entries`, labelled relative to
+    /// `base`, or closes it if it's the one already open.
+    fn toggle_flat_view(&mut self, title: &'static str, base: PathBuf, entries: Vec<DirEntryInfo>) {
+        if self
+            .flat_view
+            .take()
+            .is_some_and(|view| view.title == title)
+        {
+            return;
+        }
+        self.flat_view = Some(FlatView {
+            title,
+            base,
+            entries,
+            notes: HashMap::new(),
+            selected: 0,
+        });
+    }
+
+    /// Re-reads the sizes of the files under the current directory, keeping
+    /// the selection on the same entry.
+    pub fn refresh_sizes(&mut self) {
+        if self.live_scan.is_some() {
+            self.message = Some("Still scanning; sizes fill in as they're read".to_string());
+            return;
+        }
+        let over_before = self.dirs_over_warning();
+        let missing = restat(&mut self.current_node, &self.scan_options);
+        self.sort_children();
+        self.sync_stack();
+        self.message = Some(match missing {
+            0 => "Sizes refreshed".to_string(),
+            n => format!("Sizes refreshed; {} files are gone, rescan to drop them", n),
+        });
+
+        let crossed: Vec<PathBuf> = self
+            .dirs_over_warning()
+            .into_iter()
+            .filter(|path| !over_before.contains(path))
+            .collect();
+        if let (Some(first), Some(limit)) = (crossed.first(), self.warn_at) {
+            let others = match crossed.len() {
+                1 => String::new(),
+                n => format!(" and {} more", n - 1),
+            };
+            self.message = Some(format!(
+                "{}{} grew past {}",
+                first.display(),
+                others,
+                self.format_size(limit)
+            ));
+            self.alert = true;
+        }
+    }
+
+    /// Why the current directory couldn't be read, if the scan failed on it.
+    pub fn read_error(&self) -> Option<&ScanError> {
+        self.errors
+            .iter()
+            .find(|err| err.path == self.current_node.path)
+    }
+
+    /// Whether `entry` is a directory above `warn_at`.
+    pub fn is_over_warning(&self, entry: &DirEntryInfo) -> bool {
+        entry.is_dir && self.warn_at.is_some_and(|limit| entry.size > limit)
+    }
+
+    /// Paths of the current directory and the directories under it that are
+    /// above `warn_at`.
+    fn dirs_over_warning(&self) -> HashSet<PathBuf> {
+        let mut over: HashSet<PathBuf> = self
+            .current_node
+            .find_all(&|e| self.is_over_warning(e))
+            .into_iter()
+            .map(|e| e.path.clone())
+            .collect();
+        if self.is_over_warning(&self.current_node) {
+            over.insert(self.current_node.path.clone());
+        }
+        over
+    }
+
+    /// Copies the selected entry's path to the clipboard, or keeps it to
+    /// print on exit when that fails.
+    pub fn copy_selected(&mut self) {
+        let Some(path) = self.selected_entry().map(|e| e.path.clone()) else {
+            return;
+        };
+        let copied = match &mut self.clipboard {
+            Some(clipboard) => clipboard.copy_path(&path),
+            None => Clipboard::new().and_then(|mut clipboard| {
+                let copied = clipboard.copy_path(&path);
+                self.clipboard = Some(clipboard);
+                copied
+            }),
+        };
+        self.message = Some(match copied {
+            Ok(()) => format!("Copied {}", path.display()),
+            Err(err) => {
+                self.uncopied.push(path);
+                format!("Couldn't copy ({}); the path is printed on exit", err)
+            }
+        });
+    }
+
+    /// Opens the file manager on the selected entry.
+    pub fn reveal_selected(&mut self) {
+        let Some(entry) = self.selected_entry() else {
+            return;
+        };
+        self.message = Some(match integration::reveal(&entry.path) {
+            Ok(()) => format!("Showing {} in the file manager", entry.path.display()),
+            Err(err) => format!("Couldn't open the file manager: {}", err),
+        });
+    }
+
+    /// Asks to confirm deleting the selected entry of the listing.
+    pub fn request_delete(&mut self) {
+        // The walk would put it back
+        if self.live_scan.is_some() {
+            self.message = Some("Wait for the scan to finish before deleting".to_string());
+            return;
+        }
+        if let Some(delete) = &self.pending_delete {
+            self.message = Some(format!("Still deleting {}", delete.path.display()));
+            return;
+        }
+        if self.flat_view.is_some() {
+            self.message = Some("Delete from the directory listing".to_string());
+            return;
+        }
+        if let Some(entry) = self.selected_child() {
+            self.confirm_delete = Some(entry.path.clone());
+        }
+    }
+
+    /// Starts removing the entry waiting for confirmation from disk in the
+    /// background; `poll_scan` takes each removed entry out of the tree.
+    pub fn delete_selected(&mut self) {
+        let Some(path) = self.confirm_delete.take() else {
+            return;
+        };
+        let Some(entry) = self.current_node.children.iter().find(|c| c.path == path) else {
+            return;
+        };
+        let total = entry.inodes;
+        let cancel = Arc::new(AtomicBool::new(false));
+        let (tx, updates) = mpsc::channel();
+        std::thread::spawn({
+            let path = path.clone();
+            let cancel = Arc::clone(&cancel);
+            move || remove_tree(&path, &cancel, tx)
+        });
+        self.pending_delete = Some(PendingDelete {
+            path,
+            removed: 0,
+            total,
+            freed: 0,
+            still_linked: 0,
+            updates,
+            cancel,
+        });
+    }
+
+    /// Stops the running delete after the entry it's on, keeping what's
+    /// left. Returns false if no delete is running or it was already asked
+    /// to stop.
+    pub fn stop_delete(&mut self) -> bool {
+        let Some(delete) = &self.pending_delete else {
+            return false;
+        };
+        if delete.cancel.swap(true, AtomicOrdering::SeqCst) {
+            return false;
+        }
+        self.message = Some(format!("Stopping the delete of {}", delete.path.display()));
+        true
+    }
+
+    /// Takes the entries the running delete has removed since the last call
+    /// out of the tree, for at most half a tick, and reports how it went
+    /// once it's over.
+    fn poll_delete(&mut self) {
+        let Some(delete) = &mut self.pending_delete else {
+            return;
+        };
+        let deadline = Instant::now() + self.tick_rate / 2;
+        let mut removed = Vec::new();
+        let outcome = loop {
+            if Instant::now() >= deadline {
+                break None;
+            }
+            match delete.updates.try_recv() {
+                Ok(DeleteUpdate::Removed { path, still_linked }) => {
+                    removed.push((path, still_linked))
+                }
+                Ok(DeleteUpdate::Done(outcome)) => break Some(outcome),
+                Err(TryRecvError::Empty) => break None,
+                // The delete thread panicked before it could report
+                Err(TryRecvError::Disconnected) => {
+                    break Some(DeleteOutcome {
+                        failures: 1,
+                        first_error: Some(std::io::Error::other("the delete stopped")),
+                        ..DeleteOutcome::default()
+                    })
+                }
+            }
+        };
+
+        if !removed.is_empty() {
+            let placeholder = self.stack[0].without_children();
+            let mut root = std::mem::replace(&mut self.stack[0], placeholder);
+            for (path, still_linked) in &removed {
+                // Entries past the depth limit have no node of their own
+                let Some(entry) = root.remove_descendant(path) else {
+                    continue;
+                };
+                if !still_linked {
+                    delete.freed += entry.size;
+                    continue;
+                }
+                delete.still_linked += 1;
+                if entry.hardlink.is_some_and(|link| link.counted) {
+                    recount_hardlink(&mut root, &entry);
+                }
+            }
+            delete.removed += removed.len() as u64;
+            let removed: Vec<PathBuf> = removed.into_iter().map(|(path, _)| path).collect();
+            self.expanded
+                .retain(|p| !removed.iter().any(|path| p.starts_with(path)));
+            let selected = self.selected;
+            let gone = self
                 .current_node
                 .children
-                .sort_by(|a, b| b.size.cmp(&a.size)),
+                .get(selected)
+                .is_some_and(|c| removed.contains(&c.path));
+            self.show_tree(root);
+            // Stay where the deleted entry was rather than jumping to the top
+            if gone {
+                self.selected = selected.min(self.current_node.children.len().saturating_sub(1));
+                self.select_visible();
+            }
+        }
+
+        let Some(outcome) = outcome else {
+            return;
+        };
+        let delete = self.pending_delete.take().unwrap();
+        let freed = self.format_size(delete.freed);
+        self.message = Some(match (outcome.cancelled, outcome.first_error) {
+            (_, Some(err)) => {
+                self.alert = true;
+                format!(
+                    "Couldn't delete {} entr{} in {} ({}); freed {}",
+                    outcome.failures,
+                    if outcome.failures == 1 { "y" } else { "ies" },
+                    delete.path.display(),
+                    err,
+                    freed
+                )
+            }
+            (true, None) => format!(
+                "Stopped deleting {} after {} of {} entries, freeing {}",
+                delete.path.display(),
+                delete.removed,
+                delete.total,
+                freed
+            ),
+            (false, None) => format!("Deleted {}, freeing {}", delete.path.display(), freed),
+        });
+        if delete.still_linked > 0 {
+            let message = self.message.take().unwrap_or_default();
+            self.message = Some(format!(
+                "{}; {} file{} still {} another name, which keeps {} space",
+                message,
+                delete.still_linked,
+                if delete.still_linked == 1 { "" } else { "s" },
+                if delete.still_linked == 1 {
+                    "has"
+                } else {
+                    "have"
+                },
+                if delete.still_linked == 1 {
+                    "its"
+                } else {
+                    "their"
+                },
+            ));
+        }
+    }
+
+    /// Copies `current_node` back into the stack and updates each ancestor's
+    /// copy of it, so changes survive navigating out.
+    fn sync_stack(&mut self) {
+        let last = self.stack.len() - 1;
+        self.stack[last] = self.current_node.clone();
+        for i in (0..last).rev() {
+            let child = self.stack[i + 1].clone();
+            self.stack[i].replace_child(child);
+        }
+    }
+
+    pub fn open_prompt(&mut self, kind: PromptKind) {
+        // Pick up editing the query where it was left
+        let input = match kind {
+            PromptKind::Filter => self.filter.clone().unwrap_or_default(),
+            _ => String::new(),
+        };
+        self.prompt = Some(Prompt { kind, input });
+    }
+
+    /// Closes the prompt and acts on what was typed.
+    pub fn submit_prompt(&mut self) {
+        if let Some(prompt) = self.prompt.take() {
+            match prompt.kind {
+                PromptKind::GoTo => self.go_to(prompt.input.trim()),
+                PromptKind::Open => self.open_root(prompt.input.trim()),
+                PromptKind::Filter => self.set_filter(Some(prompt.input)),
+            }
+        }
+    }
+
+    /// Jumps to `input`, absolute or relative to the current directory,
+    /// rebuilding the stack from the root. A file opens its directory with
+    /// the file selected.
+    pub fn go_to(&mut self, input: &str) {
+        if input.is_empty() {
+            return;
+        }
+        let target = normalize(&self.current_node.path.join(input));
+        let root = self.stack[0].clone();
+        let Ok(rest) = target.strip_prefix(&root.path) else {
+            self.message = Some(format!("{} is outside this scan", target.display()));
+            return;
+        };
+
+        let mut stack = vec![root];
+        let mut file = None;
+        let mut components = rest.components().peekable();
+        while let Some(component) = components.next() {
+            let found = stack
+                .last()
+                .unwrap()
+                .children
+                .iter()
+                .find(|c| c.path.file_name() == Some(component.as_os_str()));
+            match found {
+                Some(child) if child.is_dir => {
+                    let child = child.clone();
+                    stack.push(child);
+                }
+                Some(child) if components.peek().is_none() => file = Some(child.path.clone()),
+                _ => {
+                    self.message = Some(format!("{} is not in this scan", target.display()));
+                    return;
+                }
+            }
+        }
+
+        self.current_node = stack.last().unwrap().clone();
+        self.stack = stack;
+        self.filter = None;
+        self.sort_children();
+        self.selected = file
+            .and_then(|path| {
+                self.current_node
+                    .children
+                    .iter()
+                    .position(|c| c.path == path)
+            })
+            .unwrap_or(0);
+    }
+
+    /// Starts scanning `input`, absolute or relative to the current
+    /// directory, with the current scan options. The current tree stays up
+    /// until the new one is ready.
+    pub fn open_root(&mut self, input: &str) {
+        if input.is_empty() {
+            return;
+        }
+        if self.still_scanning() {
+            return;
+        }
+        let path = self.current_node.path.join(input);
+        let resolved = if self.no_canonicalize {
+            std::path::absolute(&path)
+        } else {
+            path.canonicalize()
+        };
+        let root = match resolved {
+            Ok(root) if root.is_dir() => root,
+            Ok(root) => {
+                self.message = Some(format!("{} is not a directory", root.display()));
+                return;
+            }
+            Err(err) => {
+                self.message = Some(format!("Can't open {}: {}", input, err));
+                return;
+            }
+        };
+
+        let options = self.scan_options.clone();
+        self.start_scan(root, options, false);
+    }
+
+    /// Scans the current directory again in the background and puts the
+    /// result in place of what the tree has for it, keeping the selection.
+    pub fn rescan_current(&mut self) {
+        if self.still_scanning() {
+            return;
+        }
+        if self.combined_roots && self.stack.len() == 1 {
+            self.message = Some("Open one of the roots to rescan it".to_string());
+            return;
+        }
+        // Keep the depth limit counted from the top of the scan
+        let depth = self.stack.len() - 1 - usize::from(self.combined_roots);
+        let options = ScanOptions {
+            max_depth: self
+                .scan_options
+                .max_depth
+                .map(|max| max.saturating_sub(depth)),
+            ..self.scan_options.clone()
+        };
+        self.start_scan(self.current_node.path.clone(), options, true);
+    }
+
+    /// Scans the whole root again from scratch, showing the tree as it's
+    /// read, e.g. to replace a cached scan that may be out of date.
+    pub fn rescan_root(&mut self) {
+        if self.still_scanning() {
+            return;
+        }
+        if self.combined_roots {
+            self.message = Some("Open one of the roots to rescan it".to_string());
+            return;
+        }
+        self.cached_at = None;
+        self.stream_scan(self.cache_scans);
+    }
+
+    /// Whether a scan is already running, saying so in the status bar. A
+    /// running delete counts too, since a scan could bring back what it's
+    /// removing.
+    fn still_scanning(&mut self) -> bool {
+        if let Some(delete) = &self.pending_delete {
+            self.message = Some(format!("Still deleting {}", delete.path.display()));
+            return true;
+        }
+        let pending = self.pending_scan.as_ref().map(|scan| &scan.root);
+        let Some(root) = pending.or(self.live_scan.as_ref().map(|scan| &scan.root)) else {
+            return false;
+        };
+        self.message = Some(format!("Still scanning {}", root.display()));
+        true
+    }
+
+    fn start_scan(&mut self, root: PathBuf, options: ScanOptions, in_place: bool) {
+        let progress = ProgressBar::hidden();
+        progress.set_message(format!("Scanning {}", root.display()));
+        let handle = std::thread::spawn({
+            let root = root.clone();
+            let progress = progress.clone();
+            move || build_tree(&root, &options, &progress)
+        });
+        self.pending_scan = Some(PendingScan {
+            root,
+            progress,
+            started: Instant::now(),
+            in_place,
+            handle,
+        });
+    }
+
+    /// Starts scanning the root in the background with the current scan
+    /// options, showing its tree as it grows. With `cache` set, the finished
+    /// scan is saved for `--reuse-cache`.
+    pub fn stream_scan(&mut self, cache: bool) {
+        let root = self.stack[0].path.clone();
+        // A token of its own, so dropping the scan stops only this walk
+        let options = ScanOptions {
+            cancel: Default::default(),
+            ..self.scan_options.clone()
+        };
+        let (tx, updates) = mpsc::channel();
+        std::thread::spawn({
+            let root = root.clone();
+            let options = options.clone();
+            move || stream_tree(&root, &options, tx)
+        });
+        self.live_scan = Some(LiveScan {
+            builder: TreeBuilder::new(&root, options.max_depth),
+            root,
+            files: 0,
+            bytes: 0,
+            updates,
+            options,
+            stale: false,
+            next_rebuild: Instant::now(),
+            cache,
+        });
+    }
+
+    /// Stops the live scan where it is, leaving the tree read so far marked
+    /// as cancelled. Returns false if no scan is running or it was already
+    /// asked to stop.
+    pub fn stop_live_scan(&mut self) -> bool {
+        let Some(scan) = &self.live_scan else {
+            return false;
+        };
+        if scan.options.cancel.swap(true, AtomicOrdering::SeqCst) {
+            return false;
+        }
+        self.message = Some("Stopping the scan; press Ctrl-C again to quit".to_string());
+        true
+    }
+
+    /// Takes in entries from the live scan for at most half a tick, so keys
+    /// still get through while the walk floods in, and shows them once the
+    /// last rebuild has been waited out. Settles the tree when it's done.
+    fn poll_live_scan(&mut self) {
+        let Some(scan) = &mut self.live_scan else {
+            return;
+        };
+        let deadline = Instant::now() + self.tick_rate / 2;
+        let outcome = loop {
+            if Instant::now() >= deadline {
+                break None;
+            }
+            match scan.updates.try_recv() {
+                Ok(ScanUpdate::Entries(entries)) => {
+                    for entry in entries {
+                        if !entry.is_dir {
+                            scan.files += 1;
+                            scan.bytes += entry.size;
+                        }
+                        scan.builder.push(entry);
+                    }
+                    scan.stale = true;
+                }
+                Ok(ScanUpdate::Done(outcome)) => break Some(outcome),
+                Err(TryRecvError::Empty) => break None,
+                // The scan thread panicked before it could report
+                Err(TryRecvError::Disconnected) => {
+                    break Some(Err(std::io::Error::other("the scan stopped")))
+                }
+            }
+        };
+
+        match outcome {
+            Some(Ok(outcome)) => self.finish_live_scan(outcome),
+            Some(Err(err)) => {
+                let scan = self.live_scan.take().unwrap();
+                self.message = Some(format!("Couldn't scan {}: {}", scan.root.display(), err));
+                self.alert = true;
+            }
+            None if scan.stale && Instant::now() >= scan.next_rebuild => {
+                let started = Instant::now();
+                let tree = scan.builder.snapshot();
+                scan.stale = false;
+                self.show_tree(tree);
+                if let Some(scan) = &mut self.live_scan {
+                    scan.next_rebuild = Instant::now() + started.elapsed() * REBUILD_SPACING;
+                }
+            }
+            None => {}
+        }
+    }
+
+    fn finish_live_scan(&mut self, outcome: WalkOutcome) {
+        let mut scan = self.live_scan.take().unwrap();
+        let builder = std::mem::replace(&mut scan.builder, TreeBuilder::new(&scan.root, None));
+        let result = scan_result(builder, outcome, &scan.options, &ProgressBar::hidden());
+        self.completeness = result.completeness.clone();
+        self.errors = result.errors.clone();
+        self.scan_times = result.scan_times.clone();
+        // Partial totals would read as the directory shrinking
+        if !result.completeness.is_partial() {
+            if let Err(err) = config::record_scan(&scan.root, result.tree.size) {
+                self.message = Some(format!("Couldn't record scan history: {}", err));
+            }
+            if scan.cache {
+                if let Err(err) = cache::save(&scan.root, &scan.options, &result) {
+                    self.message = Some(format!("Couldn't cache the scan: {}", err));
+                }
+            }
+        }
+        self.show_tree(result.tree);
+    }
+
+    /// Swaps in a newer tree of the same root, staying in the current
+    /// directory, or the deepest one above it still in the tree, with the
+    /// same entry selected.
+    fn show_tree(&mut self, tree: DirEntryInfo) {
+        let selected = self
+            .current_node
+            .children
+            .get(self.selected)
+            .map(|e| e.path.clone());
+        let mut stack = vec![tree];
+        for level in &self.stack[1..] {
+            let Some(node) = stack
+                .last()
+                .unwrap()
+                .children
+                .iter()
+                .find(|c| c.path == level.path)
+            else {
+                break;
+            };
+            let node = node.clone();
+            stack.push(node);
+        }
+        // Going back out lists each level as it's kept here
+        let sort_by = self.sort_by;
+        for node in &mut stack {
+            node.children.sort_by(|a, b| sort_by.compare(a, b));
+        }
+        self.current_node = stack.last().unwrap().clone();
+        self.stack = stack;
+        self.selected = selected
+            .and_then(|path| {
+                self.current_node
+                    .children
+                    .iter()
+                    .position(|c| c.path == path)
+            })
+            .unwrap_or(0);
+        self.select_visible();
+    }
+
+    /// Takes in progress from the live scan, and swaps in the tree from a
+    /// background scan once it has finished.
+    pub fn poll_scan(&mut self) {
+        self.poll_live_scan();
+        self.poll_delete();
+        if !self
+            .pending_scan
+            .as_ref()
+            .is_some_and(|scan| scan.handle.is_finished())
+        {
+            return;
+        }
+        let scan = self.pending_scan.take().unwrap();
+        match scan.handle.join() {
+            Ok(Ok(result)) if scan.in_place => self.splice_scan(result),
+            Ok(Ok(result)) => self.load_scan(&scan.root, result),
+            Ok(Err(err)) => {
+                self.message = Some(format!("Couldn't scan {}: {}", scan.root.display(), err));
+            }
+            Err(_) => {
+                self.message = Some(format!("Scanning {} failed", scan.root.display()));
+            }
+        }
+    }
+
+    /// Puts a rescanned directory in place of its old copy in the tree,
+    /// adjusting the totals above it, and swaps its errors in for the
+    /// old ones.
+    fn splice_scan(&mut self, scan: ScanResult) {
+        let path = scan.tree.path.clone();
+        let placeholder = self.stack[0].without_children();
+        let mut root = std::mem::replace(&mut self.stack[0], placeholder);
+        let mut tree = scan.tree;
+        let Some(old) = root.find(&path).map(DirEntryInfo::without_children) else {
+            self.stack[0] = root;
+            self.message = Some(format!("{} is no longer in the tree", path.display()));
+            return;
+        };
+        // The scan started there, so it can't tell
+        tree.mount_point = old.mount_point;
+        if path == root.path {
+            self.cached_at = None;
+        }
+        let size = tree.size;
+        root.replace_descendant(tree);
+
+        self.errors.retain(|err| !err.path.starts_with(&path));
+        self.errors.extend(scan.errors);
+        self.completeness.errors = self.errors.len();
+        self.completeness.timed_out |= scan.completeness.timed_out;
+        self.completeness.truncated |= scan.completeness.truncated;
+        self.completeness.cancelled |= scan.completeness.cancelled;
+        self.completeness.ignore_files = self
+            .completeness
+            .ignore_files
+            .max(scan.completeness.ignore_files);
+        self.scan_times.extend(scan.scan_times);
+        self.show_tree(root);
+        self.message = Some(format!(
+            "Rescanned {}: {} (was {})",
+            path.display(),
+            self.format_size(size),
+            self.format_size(old.size)
+        ));
+    }
+
+    /// Replaces the tree and everything tied to it, keeping display
+    /// settings such as the sort order and units.
+    fn load_scan(&mut self, root: &Path, scan: ScanResult) {
+        self.current_node = scan.tree.clone();
+        self.stack = vec![scan.tree];
+        // A root opened with `O` is scanned on its own
+        self.combined_roots = false;
+        self.cached_at = None;
+        self.selected = 0;
+        self.filter = None;
+        self.table_state = TableState::default();
+        self.list_state = ListState::default();
+        self.list_layout = ListLayout::default();
+        self.completeness = scan.completeness;
+        self.show_completeness = false;
+        self.errors = scan.errors;
+        self.scan_times = scan.scan_times;
+        self.expanded.clear();
+        self.flat_view = None;
+        if self.show_inodes {
+            self.free_inodes = volume::free_inodes(root);
+        }
+        self.sort_children();
+        self.message = Some(format!("Opened {}", root.display()));
+        if !self.completeness.is_partial() {
+            if let Err(err) = config::record_scan(root, self.current_node.size) {
+                self.message = Some(format!(
+                    "Opened {}; couldn't record history: {}",
+                    root.display(),
+                    err
+                ));
+            }
+        }
+    }
+}
+
+/// Moves the size of `removed`, the name a hard-linked file was counted
+/// under, to another of its names still in `root`, so the file keeps
+/// counting once. Nothing moves if the other names are outside the scan.
+fn recount_hardlink(root: &mut DirEntryInfo, removed: &DirEntryInfo) {
+    let Some(link) = removed.hardlink else {
+        return;
+    };
+    let same_file = |e: &DirEntryInfo| {
+        e.hardlink
+            .is_some_and(|l| (l.device, l.inode) == (link.device, link.inode))
+    };
+    let Some(survivor) = root
+        .find_all(&same_file)
+        .first()
+        .map(|e| e.without_children())
+    else {
+        return;
+    };
+    root.replace_descendant(DirEntryInfo {
+        size: removed.size,
+        allocated_size: removed.allocated_size,
+        rounded_size: removed.rounded_size,
+        hardlink: Some(Hardlink {
+            counted: true,
+            ..link
+        }),
+        ..survivor
+    });
+}
+
+/// Resolves `.` and `..` without touching the filesystem, since the scanned
+/// tree may no longer match it.
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{dir_entry, file_entry, TempDir};
+    use crate::ui::{event::Action, keymap::BINDINGS};
+
+    fn tree() -> DirEntryInfo {
+        dir_entry(
+            "/r",
+            vec![
+                dir_entry(
+                    "/r/d",
+                    vec![file_entry("/r/d/a", 1), file_entry("/r/d/b", 5)],
+                ),
+                file_entry("/r/f", 2),
+            ],
+        )
+    }
+
+    fn names(app: &App) -> Vec<String> {
+        app.current_node
+            .children
+            .iter()
+            .map(|c| c.path.display().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn entering_a_directory_sorts_it() {
+        let mut app = App::new(tree());
+        app.sort_children();
+        assert!(app.navigate_into());
+        assert_eq!(names(&app), ["/r/d/b", "/r/d/a"]);
+        assert_eq!(app.selected, 0);
+    }
+
+    #[test]
+    fn leaving_a_directory_applies_the_current_sort() {
+        let mut app = App::new(tree());
+        app.sort_children();
+        app.navigate_into();
+        app.toggle_sort_direction();
+        assert!(app.navigate_out());
+        assert_eq!(names(&app), ["/r/f", "/r/d"]);
+        assert_eq!(app.selected, 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn opening_a_root_honours_no_canonicalize() {
+        let dir = TempDir::new("open-root");
+        dir.file("real/f", 1);
+        std::os::unix::fs::symlink(dir.path().join("real"), dir.path().join("link")).unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let opened = |no_canonicalize| {
+            let mut app = App::new(dir_entry(root.to_str().unwrap(), Vec::new()));
+            app.no_canonicalize = no_canonicalize;
+            app.open_root("link");
+            app.pending_scan.take().unwrap().root
+        };
+
+        assert_eq!(opened(false), root.join("real"));
+        assert_eq!(opened(true), root.join("link"));
+    }
+
+    /// A root holding `n` files, listed largest first as f0, f1, ...
+    fn flat_tree(n: usize) -> App {
+        let files = (0..n)
+            .map(|i| file_entry(&format!("/r/f{i}"), (n - i) as u64 * 10))
+            .collect();
+        let mut app = App::new(dir_entry("/r", files));
+        app.sort_children();
+        app
+    }
+
+    #[test]
+    fn clicks_map_to_the_rows_on_screen() {
+        let mut app = flat_tree(20);
+        app.list_layout = ListLayout {
+            area: Rect::new(2, 3, 40, 10),
+            row_height: 2,
+            offset: 4,
+            targets: (0..20).map(Some).chain([None]).collect(),
+            ..ListLayout::default()
+        };
+
+        // The third row on screen, one line into it
+        app.click(10, 3 + 2 * 2 + 1);
+        assert_eq!(app.selected, 6);
+        app.click(41, 3);
+        assert_eq!(app.selected, 4);
+        // Left of, right of and below the rows
+        for (column, row) in [(1, 5), (42, 5), (10, 13)] {
+            app.click(column, row);
+            assert_eq!(app.selected, 4);
+        }
+    }
+
+    #[test]
+    fn clicking_a_summary_row_selects_nothing() {
+        let mut app = flat_tree(3);
+        app.list_layout = ListLayout {
+            area: Rect::new(0, 0, 40, 10),
+            row_height: 1,
+            targets: vec![Some(0), Some(1), None],
+            ..ListLayout::default()
+        };
+        app.click(0, 1);
+        app.click(0, 2);
+        assert_eq!(app.selected, 1);
+    }
+
+    #[test]
+    fn single_steps_wrap_and_pages_clamp() {
+        let mut app = flat_tree(25);
+        app.list_layout.area = Rect::new(0, 0, 40, 10);
+        app.list_layout.row_height = 1;
+
+        app.move_selection(-1);
+        assert_eq!(app.selected, 24);
+        app.move_selection(1);
+        assert_eq!(app.selected, 0);
+
+        app.move_page(1);
+        assert_eq!(app.selected, 10);
+        app.move_page(2);
+        assert_eq!(app.selected, 24);
+        app.move_page(-1);
+        assert_eq!(app.selected, 14);
+        app.move_page(-5);
+        assert_eq!(app.selected, 0);
+
+        app.select_last();
+        assert_eq!(app.selected, 24);
+        app.select_first();
+        assert_eq!(app.selected, 0);
+    }
+
+    #[test]
+    fn pages_count_rows_of_the_detailed_list() {
+        let mut app = flat_tree(25);
+        app.list_layout.area = Rect::new(0, 0, 40, 10);
+        app.list_layout.row_height = 2;
+        app.move_page(1);
+        assert_eq!(app.selected, 5);
+    }
+
+    #[test]
+    fn leaving_a_directory_selects_it() {
+        let tree = dir_entry(
+            "/r",
+            vec![
+                file_entry("/r/c", 1),
+                dir_entry(
+                    "/r/a",
+                    vec![
+                        file_entry("/r/a/x", 100),
+                        dir_entry("/r/a/b", vec![file_entry("/r/a/b/f", 5)]),
+                    ],
+                ),
+            ],
+        );
+        let mut app = App::new(tree);
+        app.sort_children();
+        app.select_first();
+        assert!(app.navigate_into());
+        app.move_selection(1);
+        assert!(app.navigate_into());
+        assert_eq!(app.current_node.path, Path::new("/r/a/b"));
+
+        assert!(app.navigate_out());
+        assert_eq!(app.current_node.path, Path::new("/r/a"));
+        assert_eq!(app.selected_child().unwrap().path, Path::new("/r/a/b"));
+        assert!(app.navigate_out());
+        assert_eq!(app.selected_child().unwrap().path, Path::new("/r/a"));
+        assert!(!app.navigate_out());
+    }
+
+    #[test]
+    fn the_filter_matches_names_ignoring_case() {
+        let tree = dir_entry(
+            "/r",
+            vec![
+                file_entry("/r/Report.PDF", 30),
+                dir_entry("/r/reports", vec![file_entry("/r/reports/q1", 20)]),
+                file_entry("/r/notes.txt", 10),
+            ],
+        );
+        let mut app = App::new(tree);
+        app.sort_children();
+        app.filter = Some("rEpOrT".to_string());
+        let children = &app.current_node.children;
+        assert!(app.matches_filter(&children[0]));
+        assert!(app.matches_filter(&children[1]));
+        assert!(!app.matches_filter(&children[2]));
+        assert_eq!(app.visible_children(), [0, 1]);
+
+        // Steps and entering only see what the filter leaves
+        app.move_selection(1);
+        app.move_selection(1);
+        assert_eq!(app.selected, 0);
+        app.move_selection(1);
+        assert!(app.navigate_into());
+        assert_eq!(app.current_node.path, Path::new("/r/reports"));
+        assert_eq!(app.filter, None);
+    }
+
+    fn sorted(sort_by: SortBy, mut entries: Vec<DirEntryInfo>) -> Vec<String> {
+        entries.sort_by(|a, b| sort_by.compare(a, b));
+        entries
+            .iter()
+            .map(|e| e.path.display().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn sorts_by_each_key_both_ways() {
+        let entries = vec![
+            file_entry("/r/b10", 5),
+            file_entry("/r/a", 1),
+            file_entry("/r/b9", 20),
+        ];
+        let by = |key, ascending| SortBy { key, ascending };
+
+        assert_eq!(
+            sorted(by(SortKey::Name, true), entries.clone()),
+            ["/r/a", "/r/b9", "/r/b10"]
+        );
+        assert_eq!(
+            sorted(by(SortKey::Name, false), entries.clone()),
+            ["/r/b10", "/r/b9", "/r/a"]
+        );
+        assert_eq!(
+            sorted(by(SortKey::Size, true), entries.clone()),
+            ["/r/a", "/r/b10", "/r/b9"]
+        );
+        assert_eq!(
+            sorted(by(SortKey::Size, false), entries),
+            ["/r/b9", "/r/b10", "/r/a"]
+        );
+    }
+
+    #[test]
+    fn toggling_keeps_the_key_or_the_direction() {
+        let mut app = App::new(tree());
+        assert!(app.sort_by == SortBy::new(SortKey::Size) && !app.sort_by.ascending);
+        app.toggle_sort_direction();
+        assert!(app.sort_by.key == SortKey::Size && app.sort_by.ascending);
+        app.toggle_sort();
+        // A new key starts in its usual direction
+        assert!(app.sort_by.key == SortKey::AvgSize && !app.sort_by.ascending);
+    }
+
+    #[test]
+    fn scans_record_mtimes_and_sort_by_them() {
+        let dir = TempDir::new("mtimes");
+        let day = Duration::from_secs(86_400);
+        let now = SystemTime::now();
+        for (name, age) in [("old", 30), ("new", 1), ("mid", 7)] {
+            let path = dir.file(name, 1);
+            let file = std::fs::File::options().write(true).open(path).unwrap();
+            file.set_modified(now - day * age).unwrap();
+        }
+
+        let tree = build_tree(dir.path(), &ScanOptions::default(), &ProgressBar::hidden())
+            .unwrap()
+            .tree;
+        let old = tree.find(&dir.path().join("old")).unwrap();
+        let age = now.duration_since(old.modified.unwrap()).unwrap();
+        assert!(age > day * 29 && age < day * 31, "{:?}", age);
+
+        let names = |ascending| -> Vec<String> {
+            let sort_by = SortBy {
+                key: SortKey::Modified,
+                ascending,
+            };
+            let mut children = tree.children.clone();
+            children.sort_by(|a, b| sort_by.compare(a, b));
+            children
+                .iter()
+                .map(|c| c.path.file_name().unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+        assert_eq!(names(true), ["old", "mid", "new"]);
+        assert_eq!(names(false), ["new", "mid", "old"]);
+        assert!(!SortBy::new(SortKey::Modified).ascending);
+    }
+
+    #[test]
+    fn a_minimum_size_hides_small_children() {
+        let mut app = flat_tree(10);
+        app.min_size = Some(45);
+        // f0..f5 are 100 down to 50 bytes
+        assert_eq!(app.visible_children(), [0, 1, 2, 3, 4, 5]);
+        assert_eq!(app.hidden_by_size(), 4);
+        app.select_last();
+        assert_eq!(app.selected, 5);
+        app.move_selection(1);
+        assert_eq!(app.selected, 0);
+    }
+
+    #[test]
+    fn top_files_ranks_leaves_across_levels() {
+        let tree = dir_entry(
+            "/r",
+            vec![
+                file_entry("/r/a", 30),
+                dir_entry(
+                    "/r/d",
+                    vec![
+                        file_entry("/r/d/b", 50),
+                        dir_entry(
+                            "/r/d/e",
+                            vec![file_entry("/r/d/e/c", 40), file_entry("/r/d/e/f", 5)],
+                        ),
+                    ],
+                ),
+                file_entry("/r/g", 40),
+            ],
+        );
+        let app = App::new(tree);
+        let paths = |n| -> Vec<String> {
+            app.top_files(n)
+                .iter()
+                .map(|f| f.path.display().to_string())
+                .collect()
+        };
+        // Equal sizes keep path order
+        assert_eq!(paths(3), ["/r/d/b", "/r/d/e/c", "/r/g"]);
+        assert_eq!(paths(10).len(), 5);
+        assert!(paths(0).is_empty());
+    }
+
+    #[test]
+    fn rolled_up_and_listed_rows_add_up_to_the_directory() {
+        let mut children = vec![file_entry("/r/big", 10_000), file_entry("/r/mid", 2_000)];
+        children.extend((0..50).map(|i| file_entry(&format!("/r/small{i}"), 20 + i)));
+        let parent = dir_entry("/r", children);
+
+        let threshold = 200;
+        let summary = rollup(&parent.children, threshold);
+        assert_eq!(summary.count, 50);
+        let listed: u64 = parent
+            .children
+            .iter()
+            .filter(|c| c.size >= threshold)
+            .map(|c| c.size)
+            .sum();
+        assert_eq!(summary.size + listed, parent.size);
+        assert_eq!(rollup(&parent.children, 0), Rollup::default());
+
+        let mut app = App::new(parent);
+        app.toggle_rollup();
+        // Under 1% of the directory's 14,225 bytes
+        assert_eq!(app.rolled_up(), summary);
+        assert_eq!(app.visible_children().len(), 2);
+    }
+
+    #[test]
+    fn stopping_the_live_scan_keeps_a_cancelled_tree() {
+        let dir = TempDir::new("stop-live-scan");
+        for i in 0..2000 {
+            dir.file(&format!("d{}/f{}", i % 10, i), 1);
+        }
+        let mut app = App::new(TreeBuilder::new(dir.path(), None).snapshot());
+        assert!(!app.stop_live_scan());
+        app.stream_scan(false);
+        assert!(app.stop_live_scan());
+        // The second press is left to quit
+        assert!(!app.stop_live_scan());
+
+        let started = Instant::now();
+        while app.live_scan.is_some() {
+            assert!(started.elapsed() < Duration::from_secs(10));
+            app.poll_scan();
+        }
+        assert!(app.completeness.cancelled);
+        assert_eq!(app.stack[0].path, dir.path());
+    }
+
+    #[test]
+    fn loading_a_scan_drops_the_combined_root() {
+        let dir = TempDir::new("load-single-root");
+        dir.file("f", 1);
+        let mut app = App::new(tree());
+        app.combined_roots = true;
+        let scan = build_tree(dir.path(), &ScanOptions::default(), &ProgressBar::hidden()).unwrap();
+        app.load_scan(dir.path(), scan);
+        assert!(!app.combined_roots);
+        assert_eq!(app.stack.len(), 1);
+    }
+
+    /// Runs the pending delete to the end.
+    fn finish_delete(app: &mut App) {
+        let started = Instant::now();
+        while app.pending_delete.is_some() {
+            assert!(started.elapsed() < Duration::from_secs(10));
+            app.poll_scan();
+        }
+    }
+
+    #[test]
+    fn deleting_takes_the_entry_out_of_the_totals() {
+        let dir = TempDir::new("app-delete");
+        dir.file("doomed/a/one", 100);
+        dir.file("doomed/two", 20);
+        dir.file("kept", 3);
+        let options = ScanOptions {
+            size_mode: crate::core::SizeMode::Apparent,
+            ..ScanOptions::default()
+        };
+        let scan = build_tree(dir.path(), &options, &ProgressBar::hidden()).unwrap();
+        let mut app = App::new(scan.tree);
+        app.sort_children();
+        let doomed = dir.path().join("doomed");
+        assert_eq!(app.current_node.children[0].path, doomed);
+
+        app.request_delete();
+        app.delete_selected();
+        assert!(app.pending_delete.is_some());
+        app.request_delete();
+        assert!(app.confirm_delete.is_none());
+        finish_delete(&mut app);
+
+        assert!(!doomed.exists());
+        assert_eq!(app.current_node.children.len(), 1);
+        assert_eq!(app.current_node.size, 3);
+        assert_eq!(app.stack[0].size, 3);
+        let message = app.message.unwrap();
+        assert!(message.starts_with("Deleted "), "{}", message);
+        assert!(message.ends_with("freeing 120 B"), "{}", message);
+    }
+
+    #[test]
+    fn a_stopped_delete_keeps_the_rest() {
+        let mut app = App::new(tree());
+        assert!(!app.stop_delete());
+        let (tx, updates) = mpsc::channel();
+        app.pending_delete = Some(PendingDelete {
+            path: PathBuf::from("/r/d"),
+            removed: 0,
+            total: 3,
+            freed: 0,
+            still_linked: 0,
+            updates,
+            cancel: Arc::default(),
+        });
+        assert!(app.stop_delete());
+        assert!(!app.stop_delete());
+
+        // The worker got one file in before it saw the stop
+        tx.send(DeleteUpdate::Removed {
+            path: PathBuf::from("/r/d/b"),
+            still_linked: false,
+        })
+        .unwrap();
+        tx.send(DeleteUpdate::Done(DeleteOutcome {
+            cancelled: true,
+            ..DeleteOutcome::default()
+        }))
+        .unwrap();
+        finish_delete(&mut app);
+
+        assert_eq!(app.stack[0].size, 3);
+        assert_eq!(app.stack[0].find(Path::new("/r/d")).unwrap().size, 1);
+        assert_eq!(
+            app.message.as_deref(),
+            Some("Stopped deleting /r/d after 1 of 3 entries, freeing 5 B")
+        );
+    }
+
+    #[test]
+    fn rescanning_the_root_replaces_a_cached_tree() {
+        let dir = TempDir::new("rescan-cached");
+        dir.file("new", 4);
+        let mut app = App::new(TreeBuilder::new(dir.path(), None).snapshot());
+        app.cached_at = Some(SystemTime::UNIX_EPOCH);
+        app.rescan_root();
+        assert!(app.cached_at.is_none());
+
+        let started = Instant::now();
+        while app.live_scan.is_some() {
+            assert!(started.elapsed() < Duration::from_secs(10));
+            app.poll_scan();
+        }
+        assert_eq!(app.current_node.children.len(), 1);
+
+        // Its synthetic top can't be scanned
+        app.combined_roots = true;
+        app.cached_at = Some(SystemTime::UNIX_EPOCH);
+        app.rescan_root();
+        assert!(app.live_scan.is_none());
+        assert!(app.cached_at.is_some());
+    }
+
+    #[test]
+    fn inverting_the_filter_lists_what_it_hides() {
+        let mut app = flat_tree(10);
+        app.toggle_invert_filter();
+        assert!(!app.invert_filter);
+
+        app.min_size = Some(45);
+        app.toggle_invert_filter();
+        assert!(app.filter_inverted());
+        assert_eq!(app.visible_children(), [6, 7, 8, 9]);
+        assert_eq!(app.hidden_by_size(), 0);
+        assert_eq!(app.selected, 6);
+
+        // Either filter failing is enough to be listed
+        app.set_filter(Some("f1".to_string()));
+        assert_eq!(app.visible_children(), [0, 2, 3, 4, 5, 6, 7, 8, 9]);
+
+        app.toggle_invert_filter();
+        assert_eq!(app.visible_children(), [1]);
+
+        // With nothing left to invert, everything is listed again
+        app.toggle_invert_filter();
+        app.set_filter(None);
+        app.min_size = None;
+        assert!(app.invert_filter);
+        assert!(!app.filter_inverted());
+        assert_eq!(app.visible_children().len(), 10);
+    }
+
+    #[test]
+    fn the_viewed_tree_is_sorted_and_filtered_at_every_level() {
+        let tree = dir_entry(
+            "/r",
+            vec![
+                dir_entry(
+                    "/r/d",
+                    vec![
+                        file_entry("/r/d/small", 1),
+                        file_entry("/r/d/big", 50),
+                        file_entry("/r/d/mid", 20),
+                    ],
+                ),
+                file_entry("/r/tiny", 2),
+                file_entry("/r/large", 30),
+            ],
+        );
+        let mut app = App::new(tree);
+        app.min_size = Some(10);
+        let paths = |entry: &DirEntryInfo| -> Vec<String> {
+            entry
+                .children
+                .iter()
+                .map(|c| c.path.display().to_string())
+                .collect()
+        };
+
+        let viewed = app.viewed_tree();
+        assert_eq!(paths(&viewed), ["/r/d", "/r/large"]);
+        assert_eq!(paths(&viewed.children[0]), ["/r/d/big", "/r/d/mid"]);
+        assert_eq!(viewed.size, 103);
+
+        app.sort_by = SortBy::new(SortKey::Name);
+        app.invert_filter = true;
+        let viewed = app.viewed_tree();
+        assert_eq!(paths(&viewed), ["/r/tiny"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn deleting_the_counted_name_of_a_hard_link_frees_nothing() {
+        let dir = TempDir::new("app-delete-hardlink");
+        let counted = dir.file("a-doomed/file", 100);
+        std::fs::hard_link(&counted, dir.path().join("b-kept")).unwrap();
+        let options = ScanOptions {
+            size_mode: crate::core::SizeMode::Apparent,
+            ..ScanOptions::default()
+        };
+        let scan = build_tree(dir.path(), &options, &ProgressBar::hidden()).unwrap();
+        let mut app = App::new(scan.tree);
+        let kept = dir.path().join("b-kept");
+        assert_eq!(app.stack[0].find(&kept).unwrap().size, 0);
+
+        app.select_first();
+        app.request_delete();
+        assert_eq!(app.confirm_delete, Some(dir.path().join("a-doomed")));
+        app.delete_selected();
+        finish_delete(&mut app);
+
+        // The other name now carries the file, which is still on disk
+        let survivor = app.stack[0].find(&kept).unwrap();
+        assert_eq!(survivor.size, 100);
+        assert!(survivor.hardlink.is_some_and(|link| link.counted));
+        assert_eq!(app.stack[0].size, 100);
+        let message = app.message.unwrap();
+        assert!(message.contains("freeing 0 B"), "{}", message);
+        assert!(
+            message.contains("1 file still has another name"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn no_errors_never_opens_an_empty_errors_view() {
+        let tree = dir_entry("/r", vec![file_entry("/r/a", 1)]);
+        let mut app = App::new(tree);
+        app.toggle_largest_files_view();
+        app.toggle_errors_view();
+        assert_eq!(app.message.as_deref(), Some("Every entry was read"));
+        assert_eq!(app.flat_view.as_ref().unwrap().title, "Largest files");
+    }
+
+    #[test]
+    fn sort_help_lists_the_cycle_in_order() {
+        let mut app = App::new(dir_entry("/r", vec![]));
+        app.sort_by = SortBy::new(SortKey::Name);
+        let mut names = Vec::new();
+        for _ in 0..5 {
+            names.push(app.sort_by.key.name());
+            app.toggle_sort();
         }
+        assert!(app.sort_by.key == SortKey::Name);
+        let help = BINDINGS
+            .iter()
+            .find(|b| b.action == Action::ToggleSort)
+            .unwrap()
+            .help;
+        assert_eq!(help, format!("Cycle the sort: {}", names.join(", ")));
     }
 }
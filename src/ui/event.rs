@@ -1,66 +1,231 @@
-use crossterm::event::{self, Event as CEvent, KeyCode, KeyEvent};
+use crossterm::event::{self, Event as CEvent, KeyCode, KeyEvent, MouseEvent, MouseEventKind};
 use std::time::{Duration, Instant};
 
+use super::keymap::Keymap;
+
+/// How often the interface redraws without input, unless set otherwise.
+pub const DEFAULT_TICK_RATE: Duration = Duration::from_millis(100);
+
+/// Consecutive poll/read failures after which the input thread gives up and
+/// closes the channel.
+const MAX_CONSECUTIVE_FAILURES: u32 = 10;
+
 pub enum Event<I> {
     Input(I),
+    /// A click or scroll; plain mouse movement isn't passed on.
+    Mouse(MouseEvent),
+    /// The terminal is now this many columns and rows.
+    Resize(u16, u16),
     Tick,
+    /// Polling or reading the terminal failed; the input thread keeps trying.
+    Error(std::io::Error),
 }
 
 pub struct Events {
     rx: std::sync::mpsc::Receiver<Event<KeyEvent>>,
-    _tx: std::sync::mpsc::Sender<Event<KeyEvent>>,
 }
 
 impl Events {
     pub fn new(tick_rate: Duration) -> Self {
+        Events::from_source(tick_rate, |timeout| {
+            event::poll(timeout).and_then(|ready| {
+                if ready {
+                    event::read().map(Some)
+                } else {
+                    Ok(None)
+                }
+            })
+        })
+    }
+
+    /// Reads events with `poll`, which waits up to the given time for the
+    /// next terminal event, and sends a tick every `tick_rate` in between.
+    fn from_source<F>(tick_rate: Duration, mut poll: F) -> Self
+    where
+        F: FnMut(Duration) -> std::io::Result<Option<CEvent>> + Send + 'static,
+    {
         let (tx, rx) = std::sync::mpsc::channel();
-        let event_tx = tx.clone();
-        
+
         std::thread::spawn(move || {
             let mut last_tick = Instant::now();
+            let mut failures = 0;
             loop {
                 let timeout = tick_rate
                     .checked_sub(last_tick.elapsed())
                     .unwrap_or_else(|| Duration::from_secs(0));
 
-                if event::poll(timeout).expect("Failed to poll for events") {
-                    if let CEvent::Key(key) = event::read().expect("Failed to read event") {
-                        event_tx.send(Event::Input(key)).expect("Failed to send key event");
+                let sent = match poll(timeout) {
+                    Ok(event) => {
+                        failures = 0;
+                        match event.and_then(forward) {
+                            Some(event) => tx.send(event),
+                            None => Ok(()),
+                        }
                     }
+                    Err(err) => {
+                        failures += 1;
+                        if failures > MAX_CONSECUTIVE_FAILURES {
+                            break;
+                        }
+                        let sent = tx.send(Event::Error(err));
+                        std::thread::sleep(tick_rate);
+                        sent
+                    }
+                };
+                // The receiver is gone, so nobody is listening anymore
+                if sent.is_err() {
+                    break;
                 }
 
                 if last_tick.elapsed() >= tick_rate {
-                    event_tx.send(Event::Tick).expect("Failed to send tick event");
+                    if tx.send(Event::Tick).is_err() {
+                        break;
+                    }
                     last_tick = Instant::now();
                 }
             }
         });
 
-        Events { rx, _tx: tx }
+        Events { rx }
     }
 
+    /// Blocks for the next event. Fails once the input thread has stopped.
     pub fn next(&self) -> Result<Event<KeyEvent>, std::sync::mpsc::RecvError> {
         self.rx.recv()
     }
 }
 
-pub fn handle_key_event(key: KeyCode) -> Option<Action> {
-    match key {
-        KeyCode::Char('q') => Some(Action::Quit),
-        KeyCode::Char('s') => Some(Action::ToggleSort),
-        KeyCode::Down | KeyCode::Char('j') => Some(Action::MoveSelection(1)),
-        KeyCode::Up | KeyCode::Char('k') => Some(Action::MoveSelection(-1)),
-        KeyCode::Right | KeyCode::Enter => Some(Action::NavigateIn),
-        KeyCode::Left | KeyCode::Backspace => Some(Action::NavigateOut),
+/// What to pass on for a terminal event; plain mouse movement and the like
+/// are dropped.
+fn forward(event: CEvent) -> Option<Event<KeyEvent>> {
+    match event {
+        CEvent::Key(key) => Some(Event::Input(key)),
+        CEvent::Mouse(mouse)
+            if matches!(
+                mouse.kind,
+                MouseEventKind::Down(_) | MouseEventKind::ScrollUp | MouseEventKind::ScrollDown
+            ) =>
+        {
+            Some(Event::Mouse(mouse))
+        }
+        CEvent::Resize(columns, rows) => Some(Event::Resize(columns, rows)),
         _ => None,
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// The action `key` is bound to in `keymap`, if any.
+pub fn handle_key_event(keymap: &Keymap, key: KeyCode) -> Option<Action> {
+    keymap.action(key)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Action {
     Quit,
+    QuitWithSelected,
     ToggleSort,
+    ToggleSortDirection,
     MoveSelection(isize),
+    /// Move by this many screenfuls, stopping at either end.
+    MovePage(isize),
+    SelectFirst,
+    SelectLast,
     NavigateIn,
     NavigateOut,
+    DumpListing,
+    ToggleExpand,
+    GoTo,
+    Search,
+    CycleMinSize,
+    InvertFilter,
+    ToggleRollup,
+    ZeroByteFiles,
+    Hardlinks,
+    LargestDirs,
+    LargestFiles,
+    OpenRoot,
+    CollapseChains,
+    DirectSizes,
+    Delete,
+    Reveal,
+    CopyPath,
+    ToggleDetailed,
+    ToggleTreemap,
+    ToggleUnits,
+    RefreshSizes,
+    Rescan,
+    RescanRoot,
+    ShowCompleteness,
+    ShowErrors,
+    ToggleHelp,
+}
+
+impl Action {
+    /// Whether the action changes files on disk; refused in read-only mode.
+    pub fn is_destructive(self) -> bool {
+        matches!(self, Action::Delete)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::{KeyModifiers, MouseButton};
+
+    #[test]
+    fn forwards_resizes_keys_and_clicks() {
+        assert!(matches!(
+            forward(CEvent::Resize(120, 40)),
+            Some(Event::Resize(120, 40))
+        ));
+        let key = KeyEvent::new(KeyCode::Char('q'), KeyModifiers::NONE);
+        assert!(matches!(forward(CEvent::Key(key)), Some(Event::Input(k)) if k == key));
+
+        let mouse = |kind| MouseEvent {
+            kind,
+            column: 3,
+            row: 4,
+            modifiers: KeyModifiers::NONE,
+        };
+        assert!(matches!(
+            forward(CEvent::Mouse(mouse(MouseEventKind::Down(
+                MouseButton::Left
+            )))),
+            Some(Event::Mouse(_))
+        ));
+        assert!(forward(CEvent::Mouse(mouse(MouseEventKind::Moved))).is_none());
+        assert!(forward(CEvent::FocusGained).is_none());
+    }
+
+    #[test]
+    fn ticks_at_the_tick_rate() {
+        let tick_rate = Duration::from_millis(20);
+        // A terminal that never has input
+        let events = Events::from_source(tick_rate, |timeout| {
+            std::thread::sleep(timeout);
+            Ok(None)
+        });
+
+        let start = Instant::now();
+        for _ in 0..10 {
+            assert!(matches!(events.next(), Ok(Event::Tick)));
+        }
+        let elapsed = start.elapsed();
+        assert!(elapsed >= tick_rate * 9, "{:?}", elapsed);
+        assert!(elapsed < tick_rate * 30, "{:?}", elapsed);
+    }
+
+    #[test]
+    fn input_arrives_between_ticks() {
+        let mut pending = vec![CEvent::Resize(80, 24)];
+        let events = Events::from_source(Duration::from_secs(60), move |timeout| {
+            match pending.pop() {
+                Some(event) => Ok(Some(event)),
+                None => {
+                    std::thread::sleep(timeout.min(Duration::from_millis(10)));
+                    Ok(None)
+                }
+            }
+        });
+        assert!(matches!(events.next(), Ok(Event::Resize(80, 24))));
+    }
 }
@@ -0,0 +1,110 @@
+use tui::{buffer::Buffer, layout::Rect, widgets::Widget};
+
+/// Every non-ASCII symbol the interface draws, so terminals without Unicode
+/// support get a consistent ASCII fallback.
+#[derive(Debug, Clone, Copy)]
+pub struct Glyphs {
+    pub ascii: bool,
+    pub spinner: &'static [&'static str],
+    pub up: &'static str,
+    pub down: &'static str,
+    pub left: &'static str,
+    pub right: &'static str,
+    pub tree_branch: &'static str,
+    pub tree_last: &'static str,
+    pub mount: &'static str,
+    pub symlink_loop: &'static str,
+    pub bar_full: &'static str,
+    pub bar_empty: &'static str,
+    pub warning: &'static str,
+    pub crumb_separator: &'static str,
+    pub ellipsis: &'static str,
+}
+
+pub const UNICODE: Glyphs = Glyphs {
+    ascii: false,
+    spinner: &["⠁", "⠂", "⠄", "⡀", "⢀", "⠠", "⠐", "⠈"],
+    up: "↑",
+    down: "↓",
+    left: "←",
+    right: "→",
+    tree_branch: "├─ ",
+    tree_last: "└─ ",
+    mount: "⊕",
+    symlink_loop: "↻",
+    bar_full: "█",
+    bar_empty: "░",
+    warning: "⚠",
+    crumb_separator: " › ",
+    ellipsis: "…",
+};
+
+pub const ASCII: Glyphs = Glyphs {
+    ascii: true,
+    spinner: &["|", "/", "-", "\\"],
+    up: "Up",
+    down: "Down",
+    left: "Left",
+    right: "Right",
+    tree_branch: "|- ",
+    tree_last: "`- ",
+    mount: "(+)",
+    symlink_loop: "(@)",
+    bar_full: "#",
+    bar_empty: ".",
+    warning: "!",
+    crumb_separator: " > ",
+    ellipsis: "...",
+};
+
+impl Glyphs {
+    /// ASCII when forced, on terminals known to lack Unicode, or when the
+    /// locale names a non-UTF-8 charset.
+    pub fn detect(force_ascii: bool) -> Glyphs {
+        if force_ascii {
+            return ASCII;
+        }
+        let term = std::env::var("TERM").unwrap_or_default();
+        if matches!(term.as_str(), "dumb" | "vt100" | "vt102" | "vt220") {
+            return ASCII;
+        }
+        let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|value| !value.is_empty());
+        match locale {
+            Some(locale) => {
+                let locale = locale.to_ascii_lowercase();
+                if locale.contains("utf-8") || locale.contains("utf8") {
+                    UNICODE
+                } else {
+                    ASCII
+                }
+            }
+            None => UNICODE,
+        }
+    }
+}
+
+/// Rewrites box-drawing characters left by bordered widgets into ASCII.
+/// Render it last, over the whole frame.
+pub struct AsciiBorders;
+
+impl Widget for AsciiBorders {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        for y in area.top()..area.bottom() {
+            for x in area.left()..area.right() {
+                let cell = buf.get_mut(x, y);
+                let replacement = match cell.symbol.as_str() {
+                    "─" | "━" | "═" => "-",
+                    "│" | "┃" | "║" => "|",
+                    "┌" | "┐" | "└" | "┘" | "╭" | "╮" | "╰" | "╯" | "├" | "┤" | "┬" | "┴" | "┼" => {
+                        "+"
+                    }
+                    _ => continue,
+                };
+                cell.set_symbol(replacement);
+            }
+        }
+    }
+}
@@ -0,0 +1,440 @@
+//! Which key does what, optionally remapped from `keys.toml` in the config
+//! directory.
+
+use std::collections::HashMap;
+
+use crossterm::event::KeyCode;
+
+use crate::{config, ui::event::Action};
+
+/// User remappings, read from the config directory.
+const KEYS_FILE: &str = "keys.toml";
+
+/// An action that can be bound to keys.
+pub struct Binding {
+    /// How `keys.toml` refers to it.
+    pub name: &'static str,
+    pub action: Action,
+    pub keys: &'static [KeyCode],
+    /// What it does, for the help screen.
+    pub help: &'static str,
+}
+
+/// Every bindable action with its default keys, in the order help lists them.
+pub const BINDINGS: &[Binding] = &[
+    Binding {
+        name: "up",
+        action: Action::MoveSelection(-1),
+        keys: &[KeyCode::Up, KeyCode::Char('k')],
+        help: "Move up",
+    },
+    Binding {
+        name: "down",
+        action: Action::MoveSelection(1),
+        keys: &[KeyCode::Down, KeyCode::Char('j')],
+        help: "Move down",
+    },
+    Binding {
+        name: "page_up",
+        action: Action::MovePage(-1),
+        keys: &[KeyCode::PageUp],
+        help: "Move up a page",
+    },
+    Binding {
+        name: "page_down",
+        action: Action::MovePage(1),
+        keys: &[KeyCode::PageDown],
+        help: "Move down a page",
+    },
+    Binding {
+        name: "first",
+        action: Action::SelectFirst,
+        keys: &[KeyCode::Home, KeyCode::Char('g')],
+        help: "Select the first entry",
+    },
+    Binding {
+        name: "last",
+        action: Action::SelectLast,
+        keys: &[KeyCode::End, KeyCode::Char('G')],
+        help: "Select the last entry",
+    },
+    Binding {
+        name: "open",
+        action: Action::NavigateIn,
+        keys: &[KeyCode::Right, KeyCode::Enter],
+        help: "Open the selected directory",
+    },
+    Binding {
+        name: "back",
+        action: Action::NavigateOut,
+        keys: &[KeyCode::Left, KeyCode::Backspace],
+        help: "Go to the parent directory",
+    },
+    Binding {
+        name: "expand",
+        action: Action::ToggleExpand,
+        keys: &[KeyCode::Char(' '), KeyCode::Char('l')],
+        help: "Show or hide the selected directory's children inline",
+    },
+    Binding {
+        name: "sort",
+        action: Action::ToggleSort,
+        keys: &[KeyCode::Char('s')],
+        help: "Cycle the sort: name, size, average file size, file count, modified",
+    },
+    Binding {
+        name: "reverse_sort",
+        action: Action::ToggleSortDirection,
+        keys: &[KeyCode::Char('S')],
+        help: "Reverse the sort direction",
+    },
+    Binding {
+        name: "go_to",
+        action: Action::GoTo,
+        keys: &[KeyCode::Char(':')],
+        help: "Go to a typed path",
+    },
+    Binding {
+        name: "filter",
+        action: Action::Search,
+        keys: &[KeyCode::Char('/')],
+        help: "Filter the listing by name",
+    },
+    Binding {
+        name: "min_size",
+        action: Action::CycleMinSize,
+        keys: &[KeyCode::Char('m')],
+        help: "Hide entries under 1, 10 or 100 MB or 1 GB",
+    },
+    Binding {
+        name: "invert_filter",
+        action: Action::InvertFilter,
+        keys: &[KeyCode::Char('!')],
+        help: "List only what the filter and minimum size hide",
+    },
+    Binding {
+        name: "rollup",
+        action: Action::ToggleRollup,
+        keys: &[KeyCode::Char('a')],
+        help: "Roll entries under 1% of the directory into one row",
+    },
+    Binding {
+        name: "zero_byte_files",
+        action: Action::ZeroByteFiles,
+        keys: &[KeyCode::Char('z')],
+        help: "List empty files here",
+    },
+    Binding {
+        name: "hardlinks",
+        action: Action::Hardlinks,
+        keys: &[KeyCode::Char('H')],
+        help: "List hard-linked files here",
+    },
+    Binding {
+        name: "largest_dirs",
+        action: Action::LargestDirs,
+        keys: &[KeyCode::Char('D')],
+        help: "List the largest directories in the scan",
+    },
+    Binding {
+        name: "largest_files",
+        action: Action::LargestFiles,
+        keys: &[KeyCode::Char('t')],
+        help: "List the largest files in the scan",
+    },
+    Binding {
+        name: "open_root",
+        action: Action::OpenRoot,
+        keys: &[KeyCode::Char('O')],
+        help: "Scan another directory",
+    },
+    Binding {
+        name: "collapse_chains",
+        action: Action::CollapseChains,
+        keys: &[KeyCode::Char('c')],
+        help: "Collapse single-child directory chains",
+    },
+    Binding {
+        name: "direct_sizes",
+        action: Action::DirectSizes,
+        keys: &[KeyCode::Char('I')],
+        help: "Size directories by their own files only",
+    },
+    Binding {
+        name: "detailed",
+        action: Action::ToggleDetailed,
+        keys: &[KeyCode::Char('w')],
+        help: "Toggle the two-line detailed list",
+    },
+    Binding {
+        name: "treemap",
+        action: Action::ToggleTreemap,
+        keys: &[KeyCode::Char('v')],
+        help: "Show or hide the treemap beside the listing",
+    },
+    Binding {
+        name: "units",
+        action: Action::ToggleUnits,
+        keys: &[KeyCode::Char('u')],
+        help: "Switch decimal/binary units",
+    },
+    Binding {
+        name: "refresh_sizes",
+        action: Action::RefreshSizes,
+        keys: &[KeyCode::Char('R')],
+        help: "Re-read the sizes of known files here",
+    },
+    Binding {
+        name: "rescan",
+        action: Action::Rescan,
+        keys: &[KeyCode::Char('r'), KeyCode::F(5)],
+        help: "Scan this directory again, picking up new and removed entries",
+    },
+    Binding {
+        name: "rescan_root",
+        action: Action::RescanRoot,
+        keys: &[KeyCode::Char('N')],
+        help: "Scan the whole root again, e.g. to replace a cached scan",
+    },
+    Binding {
+        name: "completeness",
+        action: Action::ShowCompleteness,
+        keys: &[KeyCode::Char('i')],
+        help: "Show why totals may be incomplete",
+    },
+    Binding {
+        name: "errors",
+        action: Action::ShowErrors,
+        keys: &[KeyCode::Char('e')],
+        help: "List the entries the scan couldn't read",
+    },
+    Binding {
+        name: "reveal",
+        action: Action::Reveal,
+        keys: &[KeyCode::Char('o')],
+        help: "Show the selected entry in the file manager",
+    },
+    Binding {
+        name: "copy_path",
+        action: Action::CopyPath,
+        keys: &[KeyCode::Char('y')],
+        help: "Copy the selected entry's path",
+    },
+    Binding {
+        name: "delete",
+        action: Action::Delete,
+        keys: &[KeyCode::Char('d')],
+        help: "Delete the selected entry",
+    },
+    Binding {
+        name: "dump_listing",
+        action: Action::DumpListing,
+        keys: &[KeyCode::Char('L')],
+        help: "Print the listing as plain text",
+    },
+    Binding {
+        name: "help",
+        action: Action::ToggleHelp,
+        keys: &[KeyCode::Char('?')],
+        help: "Show this help",
+    },
+    Binding {
+        name: "quit",
+        action: Action::Quit,
+        keys: &[KeyCode::Char('q')],
+        help: "Quit",
+    },
+    Binding {
+        name: "quit_with_selected",
+        action: Action::QuitWithSelected,
+        keys: &[KeyCode::Char('P')],
+        help: "Quit, printing the selected path",
+    },
+];
+
+/// Keys bound to actions.
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    bindings: HashMap<KeyCode, Action>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let bindings = BINDINGS
+            .iter()
+            .flat_map(|b| b.keys.iter().map(move |key| (*key, b.action)))
+            .collect();
+        Keymap { bindings }
+    }
+}
+
+impl Keymap {
+    /// The defaults with the remappings from `keys.toml` applied, or just
+    /// the defaults if there's no such file.
+    pub fn load() -> Result<Keymap, String> {
+        let Some(file) = config::config_dir().map(|dir| dir.join(KEYS_FILE)) else {
+            return Ok(Keymap::default());
+        };
+        match std::fs::read_to_string(&file) {
+            Ok(contents) => {
+                Keymap::parse(&contents).map_err(|err| format!("{}: {}", file.display(), err))
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Keymap::default()),
+            Err(err) => Err(format!("{}: {}", file.display(), err)),
+        }
+    }
+
+    /// The defaults with the remappings in `input` applied. Each line names
+    /// an action and the key or keys that replace its defaults, e.g.
+    /// `quit = "x"` or `down = ["Down", "h"]`.
+    pub fn parse(input: &str) -> Result<Keymap, String> {
+        let table: toml::Table = input
+            .parse()
+            .map_err(|err: toml::de::Error| err.message().to_string())?;
+        let mut remapped = Vec::new();
+        for (name, value) in &table {
+            let Some(binding) = BINDINGS.iter().find(|b| b.name == name) else {
+                return Err(format!("unknown action `{}`", name));
+            };
+            let keys = match value {
+                toml::Value::String(key) => vec![key.as_str()],
+                toml::Value::Array(keys) => keys
+                    .iter()
+                    .map(|key| key.as_str())
+                    .collect::<Option<Vec<&str>>>()
+                    .ok_or_else(|| format!("keys for `{}` must be strings", name))?,
+                _ => return Err(format!("`{}` needs a key name or a list of them", name)),
+            };
+            let keys = keys
+                .into_iter()
+                .map(|key| parse_key(key).ok_or_else(|| format!("unknown key `{}`", key)))
+                .collect::<Result<Vec<KeyCode>, String>>()?;
+            remapped.push((binding.action, keys));
+        }
+
+        // Drop every remapped action's defaults first, so a new key can
+        // take over another action's default without being dropped too
+        let mut keymap = Keymap::default();
+        keymap
+            .bindings
+            .retain(|_, bound| !remapped.iter().any(|(action, _)| action == bound));
+        for (action, keys) in remapped {
+            for key in keys {
+                keymap.bindings.insert(key, action);
+            }
+        }
+        Ok(keymap)
+    }
+
+    pub fn action(&self, key: KeyCode) -> Option<Action> {
+        self.bindings.get(&key).copied()
+    }
+
+    /// The keys bound to `action`, in a stable order.
+    pub fn keys_for(&self, action: Action) -> Vec<KeyCode> {
+        let mut keys: Vec<KeyCode> = self
+            .bindings
+            .iter()
+            .filter(|(_, bound)| **bound == action)
+            .map(|(key, _)| *key)
+            .collect();
+        // Defaults first, as listed, then remapped keys by name
+        let default_pos = |key: &KeyCode| {
+            BINDINGS
+                .iter()
+                .find(|b| b.action == action)
+                .and_then(|b| b.keys.iter().position(|k| k == key))
+                .unwrap_or(usize::MAX)
+        };
+        keys.sort_by_key(|key| (default_pos(key), format!("{:?}", key)));
+        keys
+    }
+}
+
+/// A key as written in `keys.toml`: a single character, or a name such as
+/// `Enter`, `Space`, `PageDown` or `F5`.
+fn parse_key(name: &str) -> Option<KeyCode> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(KeyCode::Char(c));
+    }
+    let key = match name.to_ascii_lowercase().as_str() {
+        "space" => KeyCode::Char(' '),
+        "enter" => KeyCode::Enter,
+        "esc" => KeyCode::Esc,
+        "tab" => KeyCode::Tab,
+        "backspace" => KeyCode::Backspace,
+        "delete" => KeyCode::Delete,
+        "insert" => KeyCode::Insert,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        other => {
+            let number = other.strip_prefix('f')?.parse().ok()?;
+            if !(1..=12).contains(&number) {
+                return None;
+            }
+            KeyCode::F(number)
+        }
+    };
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::event::handle_key_event;
+
+    #[test]
+    fn remaps_quit_to_x() {
+        let keymap = Keymap::parse("quit = \"x\"").unwrap();
+        assert_eq!(
+            handle_key_event(&keymap, KeyCode::Char('x')),
+            Some(Action::Quit)
+        );
+        // The remapping replaces the default key
+        assert_eq!(handle_key_event(&keymap, KeyCode::Char('q')), None);
+        assert_eq!(
+            handle_key_event(&keymap, KeyCode::Down),
+            Some(Action::MoveSelection(1))
+        );
+    }
+
+    #[test]
+    fn a_remapped_key_takes_over_a_default_binding() {
+        let keymap = Keymap::parse("down = [\"Down\", \"k\"]\nup = \"F2\"").unwrap();
+        assert_eq!(
+            keymap.action(KeyCode::Char('k')),
+            Some(Action::MoveSelection(1))
+        );
+        assert_eq!(
+            keymap.action(KeyCode::F(2)),
+            Some(Action::MoveSelection(-1))
+        );
+        assert_eq!(keymap.action(KeyCode::Up), None);
+        assert_eq!(
+            keymap.keys_for(Action::MoveSelection(1)),
+            [KeyCode::Down, KeyCode::Char('k')]
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_actions_and_keys() {
+        assert!(Keymap::parse("launch = \"x\"").is_err());
+        assert!(Keymap::parse("quit = \"F13\"").is_err());
+        assert!(Keymap::parse("quit = 1").is_err());
+    }
+
+    #[test]
+    fn g_and_shift_g_jump_to_the_ends_like_vim() {
+        let keymap = Keymap::default();
+        assert_eq!(keymap.action(KeyCode::Char('g')), Some(Action::SelectFirst));
+        assert_eq!(keymap.action(KeyCode::Char('G')), Some(Action::SelectLast));
+        assert_eq!(keymap.keys_for(Action::GoTo), [KeyCode::Char(':')]);
+    }
+}
@@ -1,3 +1,8 @@
 pub mod app;
 pub mod event;
+pub mod glyphs;
+pub mod keymap;
+pub mod theme;
+pub mod treemap;
+#[allow(clippy::module_inception)]
 pub mod ui;
@@ -0,0 +1,116 @@
+//! How the listing's rows are colored.
+
+use tui::style::{Color, Modifier, Style};
+
+/// Coloring for the listing's rows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Theme {
+    /// Color entries by size next to the largest one listed: red for the
+    /// biggest, then yellow, then green.
+    #[default]
+    Heat,
+    /// Blue directories and green recent files.
+    Classic,
+    /// No colors, only bold, italic, underlined and dim text.
+    Plain,
+}
+
+/// What the listing knows about an entry when styling its row.
+pub struct RowTraits {
+    pub is_dir: bool,
+    pub empty: bool,
+    pub recent: bool,
+    pub over_warning: bool,
+    /// Size next to the largest entry listed, from 0 to 1.
+    pub fraction: f64,
+}
+
+/// Red for the top third of sizes, yellow for the middle, green below.
+pub fn size_to_color(fraction: f64) -> Color {
+    if fraction >= 2.0 / 3.0 {
+        Color::Red
+    } else if fraction >= 1.0 / 3.0 {
+        Color::Yellow
+    } else {
+        Color::Green
+    }
+}
+
+impl Theme {
+    /// `Plain` when colors are turned off, by flag or through the `NO_COLOR`
+    /// convention, unless a theme was picked explicitly.
+    pub fn detect(no_color: bool, theme: Option<Theme>) -> Theme {
+        if no_color {
+            return Theme::Plain;
+        }
+        theme.unwrap_or_else(|| {
+            let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+            if no_color {
+                Theme::Plain
+            } else {
+                Theme::default()
+            }
+        })
+    }
+
+    pub fn row_style(self, row: &RowTraits) -> Style {
+        let bold = |style: Style| {
+            if row.is_dir {
+                style.add_modifier(Modifier::BOLD)
+            } else {
+                style
+            }
+        };
+        let italic = |style: Style| {
+            if row.recent {
+                style.add_modifier(Modifier::ITALIC)
+            } else {
+                style
+            }
+        };
+        match self {
+            Theme::Classic => {
+                if row.over_warning {
+                    Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)
+                } else if row.is_dir {
+                    Style::default()
+                        .fg(Color::Blue)
+                        .add_modifier(Modifier::BOLD)
+                } else if row.empty {
+                    Style::default().fg(Color::DarkGray)
+                } else if row.recent {
+                    Style::default().fg(Color::Green)
+                } else {
+                    Style::default()
+                }
+            }
+            // The gradient already uses red, so warnings stand out on it
+            Theme::Heat if row.over_warning => Style::default()
+                .fg(Color::White)
+                .bg(Color::Red)
+                .add_modifier(Modifier::BOLD),
+            Theme::Heat if row.empty => bold(Style::default().fg(Color::DarkGray)),
+            Theme::Heat => italic(bold(Style::default().fg(size_to_color(row.fraction)))),
+            Theme::Plain if row.over_warning => {
+                bold(Style::default().add_modifier(Modifier::UNDERLINED))
+            }
+            Theme::Plain if row.empty => bold(Style::default().add_modifier(Modifier::DIM)),
+            Theme::Plain => italic(bold(Style::default())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_colors_change_at_the_thirds() {
+        assert_eq!(size_to_color(0.0), Color::Green);
+        assert_eq!(size_to_color(0.333), Color::Green);
+        assert_eq!(size_to_color(1.0 / 3.0), Color::Yellow);
+        assert_eq!(size_to_color(0.666), Color::Yellow);
+        assert_eq!(size_to_color(2.0 / 3.0), Color::Red);
+        assert_eq!(size_to_color(1.0), Color::Red);
+    }
+}
@@ -0,0 +1,182 @@
+//! Squarified treemap layout on terminal cells.
+
+use tui::layout::Rect;
+
+/// Terminal cells are about twice as tall as they are wide; laying out in
+/// this stretched space keeps the tiles roughly square on screen.
+const CELL_ASPECT: f64 = 2.0;
+
+/// A rectangle in the stretched layout space.
+#[derive(Debug, Clone, Copy)]
+struct Area {
+    x: f64,
+    y: f64,
+    w: f64,
+    h: f64,
+}
+
+/// Splits `area` into one tile per entry of `sizes`, each covering a share
+/// of the cells in proportion to its size, using the squarified algorithm
+/// (Bruls, Huizing and van Wijk) to keep tiles close to square. `sizes`
+/// should be sorted largest first. Tiles are returned in the same order;
+/// they don't overlap, together cover `area`, and may be empty for
+/// entries too small to get a cell.
+pub fn squarify(area: Rect, sizes: &[u64]) -> Vec<Rect> {
+    let total: u64 = sizes.iter().sum();
+    let mut tiles = vec![Rect::new(area.x, area.y, 0, 0); sizes.len()];
+    if total == 0 || area.area() == 0 {
+        return tiles;
+    }
+
+    let mut free = Area {
+        x: 0.0,
+        y: 0.0,
+        w: area.width as f64,
+        h: area.height as f64 * CELL_ASPECT,
+    };
+    // Sizes in units of layout area
+    let scale = free.w * free.h / total as f64;
+    let weights: Vec<f64> = sizes.iter().map(|&s| s as f64 * scale).collect();
+
+    let mut start = 0;
+    while start < weights.len() {
+        let side = free.w.min(free.h);
+        // Grow the row while that makes its worst tile squarer
+        let mut end = start + 1;
+        while end < weights.len()
+            && worst_ratio(&weights[start..=end], side) <= worst_ratio(&weights[start..end], side)
+        {
+            end += 1;
+        }
+        let row = &weights[start..end];
+        let row_total: f64 = row.iter().sum();
+        if row_total <= 0.0 {
+            // Only empty entries are left
+            break;
+        }
+        let mut offset = 0.0;
+        if free.w >= free.h {
+            // A column along the left edge
+            let width = row_total / free.h;
+            for (i, weight) in row.iter().enumerate() {
+                let height = weight / width;
+                tiles[start + i] = to_cells(
+                    area,
+                    Area {
+                        x: free.x,
+                        y: free.y + offset,
+                        w: width,
+                        h: height,
+                    },
+                );
+                offset += height;
+            }
+            free.x += width;
+            free.w -= width;
+        } else {
+            // A row along the top edge
+            let height = row_total / free.w;
+            for (i, weight) in row.iter().enumerate() {
+                let width = weight / height;
+                tiles[start + i] = to_cells(
+                    area,
+                    Area {
+                        x: free.x + offset,
+                        y: free.y,
+                        w: width,
+                        h: height,
+                    },
+                );
+                offset += width;
+            }
+            free.y += height;
+            free.h -= height;
+        }
+        start = end;
+    }
+    tiles
+}
+
+/// The most elongated tile's aspect ratio when `row` is laid along a side
+/// of length `side`.
+fn worst_ratio(row: &[f64], side: f64) -> f64 {
+    let total: f64 = row.iter().sum();
+    let largest = row.iter().copied().fold(0.0, f64::max);
+    let smallest = row.iter().copied().fold(f64::INFINITY, f64::min);
+    if total <= 0.0 || smallest <= 0.0 {
+        return f64::INFINITY;
+    }
+    let side = side * side;
+    let total = total * total;
+    (side * largest / total).max(total / (side * smallest))
+}
+
+/// Snaps a layout rectangle to cells. Edges are rounded rather than sizes,
+/// so neighbours share an edge and no cell is claimed twice.
+fn to_cells(area: Rect, tile: Area) -> Rect {
+    let snap = |value: f64, limit: u16| (value.round().max(0.0) as u16).min(limit);
+    let left = snap(tile.x, area.width);
+    let right = snap(tile.x + tile.w, area.width);
+    let top = snap(tile.y / CELL_ASPECT, area.height);
+    let bottom = snap((tile.y + tile.h) / CELL_ASPECT, area.height);
+    Rect::new(
+        area.x + left,
+        area.y + top,
+        right.saturating_sub(left),
+        bottom.saturating_sub(top),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_partition(area: Rect, sizes: &[u64]) -> Vec<Rect> {
+        let tiles = squarify(area, sizes);
+        assert_eq!(tiles.len(), sizes.len());
+        for (i, a) in tiles.iter().enumerate() {
+            assert_eq!(a.intersection(area), *a, "tile {} outside the area", i);
+            for b in &tiles[i + 1..] {
+                assert!(!a.intersects(*b), "{:?} overlaps {:?}", a, b);
+            }
+        }
+        let covered: u32 = tiles.iter().map(|t| u32::from(t.area())).sum();
+        assert_eq!(covered, u32::from(area.area()));
+        tiles
+    }
+
+    #[test]
+    fn tiles_cover_the_area_without_overlap() {
+        check_partition(Rect::new(3, 2, 80, 24), &[50, 25, 12, 8, 3, 2]);
+        check_partition(Rect::new(0, 0, 17, 5), &[7, 7, 7, 1]);
+        check_partition(Rect::new(0, 0, 1, 1), &[3, 1]);
+    }
+
+    #[test]
+    fn tile_areas_follow_the_sizes() {
+        let area = Rect::new(0, 0, 100, 40);
+        let sizes = [400, 300, 200, 100];
+        let tiles = check_partition(area, &sizes);
+        let total: u64 = sizes.iter().sum();
+        for (tile, size) in tiles.iter().zip(sizes) {
+            let expected = f64::from(area.area()) * size as f64 / total as f64;
+            let actual = f64::from(tile.area());
+            assert!(
+                (actual - expected).abs() <= expected * 0.1,
+                "{} cells for a share of {}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn empty_entries_get_empty_tiles() {
+        let tiles = squarify(Rect::new(0, 0, 10, 10), &[5, 0]);
+        assert_eq!(tiles[0].area(), 100);
+        assert_eq!(tiles[1].area(), 0);
+        assert!(squarify(Rect::new(0, 0, 10, 10), &[0, 0])
+            .iter()
+            .all(|t| t.area() == 0));
+    }
+}
@@ -1,15 +1,45 @@
+use std::cmp::Reverse;
+use std::time::Duration;
+
+use crossterm::event::KeyCode;
 use tui::{
     backend::Backend,
-    layout::{Constraint, Direction, Layout, Rect},
+    buffer::Buffer,
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
-    text::Span,
-    widgets::{Block, Borders, Cell, Paragraph, Row, Table, TableState},
+    text::{Span, Spans},
+    widgets::{Block, Borders, Cell, Clear, List, ListItem, Paragraph, Row, Table, Widget},
     Frame,
 };
 
-use crate::ui::app::App;
+use crate::{
+    core::{
+        units::{format_age, format_count, format_timestamp},
+        DirEntryInfo,
+    },
+    ui::{
+        app::App,
+        event::Action,
+        glyphs::{AsciiBorders, Glyphs},
+        keymap::BINDINGS,
+        theme::{RowTraits, Theme},
+        treemap,
+    },
+};
+
+const HIGHLIGHT_SYMBOL: &str = ">> ";
+
+/// Position of the share-of-parent bar among the listing's columns.
+const SHARE_COLUMN: usize = 2;
 
-pub fn draw_ui<B: Backend>(f: &mut Frame<B>, app: &App) {
+/// Width of the file-count column, which pads its figures to the right.
+const FILES_WIDTH: u16 = 10;
+
+/// How long each spinner frame shows while a background scan runs, as
+/// fast as the one during the first scan.
+const SPINNER_FRAME: Duration = Duration::from_millis(80);
+
+pub fn draw_ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .margin(1)
@@ -23,101 +53,1097 @@ pub fn draw_ui<B: Backend>(f: &mut Frame<B>, app: &App) {
         )
         .split(f.size());
 
-    draw_header(f, chunks[0], &app.current_node.path);
-    draw_file_list(f, chunks[1], app);
+    draw_header(f, chunks[0], app);
+    if app.show_treemap {
+        let panes = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(60), Constraint::Percentage(40)].as_ref())
+            .split(chunks[1]);
+        draw_file_list(f, panes[0], app);
+        draw_treemap(f, panes[1], app);
+    } else {
+        draw_file_list(f, chunks[1], app);
+    }
     draw_status_bar(f, chunks[2], app);
+    if app.show_completeness {
+        draw_completeness(f, chunks[1], app);
+    }
+    if app.confirm_delete.is_some() {
+        draw_delete_confirmation(f, chunks[1], app);
+    }
+    if app.show_help {
+        draw_help(f, f.size(), app);
+    }
+
+    if app.glyphs.ascii {
+        f.render_widget(AsciiBorders, f.size());
+    }
 }
 
-fn draw_header<B: Backend>(f: &mut Frame<B>, area: Rect, current_path: &std::path::Path) {
+fn draw_header<B: Backend>(f: &mut Frame<B>, area: Rect, app: &App) {
+    let mut title = vec![Span::raw(format!(
+        " Disk Usage Analyzer ({} to quit) ",
+        first_key(app, Action::Quit)
+    ))];
+    let banner_style = Style::default()
+        .fg(Color::Black)
+        .bg(Color::Yellow)
+        .add_modifier(Modifier::BOLD);
+    if app.completeness.timed_out {
+        title.push(Span::styled(" partial (timed out) ", banner_style));
+    }
+    if app.completeness.truncated {
+        title.push(Span::styled(" partial (entry limit) ", banner_style));
+    }
+    if app.completeness.cancelled {
+        title.push(Span::styled(" partial (cancelled) ", banner_style));
+    }
+    if let Some(scanned) = app.cached_at {
+        title.push(Span::styled(
+            format!(
+                " cached {}, may be stale ({}: rescan) ",
+                format_timestamp(scanned),
+                first_key(app, Action::RescanRoot)
+            ),
+            banner_style,
+        ));
+    }
+    if app.read_only {
+        title.push(Span::styled(
+            " read-only ",
+            Style::default()
+                .fg(Color::Black)
+                .bg(Color::Cyan)
+                .add_modifier(Modifier::BOLD),
+        ));
+    }
     let header = Block::default()
         .borders(Borders::ALL)
-        .title(" Disk Usage Analyzer (q to quit)");
+        .title(Spans::from(title));
+    let inner = header.inner(area);
+    f.render_widget(header, area);
 
-    let path_text = Paragraph::new(current_path.display().to_string())
-        .block(Block::default().borders(Borders::BOTTOM));
+    // The root as scanned, then one name per level below it
+    let segments: Vec<String> = app
+        .stack
+        .iter()
+        .enumerate()
+        .map(|(i, node)| match node.path.file_name() {
+            Some(name) if i > 0 => name.to_string_lossy().into_owned(),
+            _ => node.path.display().to_string(),
+        })
+        .collect();
+    let g = &app.glyphs;
+    let visible = breadcrumb(&segments, usize::from(inner.width), g);
+    let last = visible.len().saturating_sub(1);
+    let mut crumbs = Vec::new();
+    for (i, segment) in visible.into_iter().enumerate() {
+        if i > 0 {
+            crumbs.push(Span::styled(
+                g.crumb_separator,
+                Style::default().fg(Color::DarkGray),
+            ));
+        }
+        let style = if i == last {
+            Style::default().add_modifier(Modifier::BOLD)
+        } else {
+            Style::default()
+        };
+        crumbs.push(Span::styled(segment, style));
+    }
+    f.render_widget(Paragraph::new(Spans::from(crumbs)), inner);
+}
 
-    f.render_widget(header, area);
-    f.render_widget(path_text, area);
+/// The segments of a breadcrumb that fit in `width` columns. When they
+/// don't all fit, the middle gives way to an ellipsis, filled from the
+/// right so the current directory always shows; the root stays too while
+/// there's room for it.
+fn breadcrumb<'a>(segments: &'a [String], width: usize, g: &Glyphs) -> Vec<&'a str> {
+    let len = |s: &str| s.chars().count();
+    let sep = len(g.crumb_separator);
+    let whole: usize =
+        segments.iter().map(|s| len(s)).sum::<usize>() + sep * segments.len().saturating_sub(1);
+    if whole <= width || segments.len() < 2 {
+        return segments.iter().map(String::as_str).collect();
+    }
+
+    let (root, rest) = segments.split_first().unwrap();
+    let mut used = len(g.ellipsis);
+    let keep_root = len(root) + sep + used + sep + len(rest.last().unwrap()) <= width;
+    if keep_root {
+        used += len(root) + sep;
+    }
+    let mut tail = Vec::new();
+    for segment in rest.iter().rev() {
+        let needed = sep + len(segment);
+        // The current directory shows even if it has to be cut off
+        if !tail.is_empty() && used + needed > width {
+            break;
+        }
+        used += needed;
+        tail.push(segment.as_str());
+    }
+    tail.reverse();
+
+    let mut visible = Vec::new();
+    if keep_root {
+        visible.push(root.as_str());
+    }
+    visible.push(g.ellipsis);
+    visible.extend(tail);
+    visible
 }
 
-fn draw_file_list<B: Backend>(f: &mut Frame<B>, area: Rect, app: &App) {
-    let header_cells = ["Name", "Size"]
-        .iter()
-        .map(|h| Cell::from(*h).style(Style::default().add_modifier(Modifier::BOLD)));
+fn draw_file_list<B: Backend>(f: &mut Frame<B>, area: Rect, app: &mut App) {
+    // Data columns are fixed width; the name column takes what's left
+    let size_heading = if app.direct_sizes {
+        "Direct Size"
+    } else {
+        "Size"
+    };
+    // The bar grows with the window, within reason
+    let share_width = (area.width / 5).clamp(10, 30);
+    let mut columns = vec![("Name", 0), (size_heading, 12), ("Share", share_width)];
+    if app.show_rounded {
+        columns.push(("On Disk", 12));
+    }
+    if app.show_inodes {
+        columns.push(("Inodes", 10));
+    }
+    if app.shows_avg_size() {
+        columns.push(("Avg File", 12));
+    }
+    if app.shows_file_count() {
+        columns.push(("Files", FILES_WIDTH));
+    }
+    if app.shows_modified() {
+        columns.push(("Modified", 15));
+    }
+    if app.show_scan_times {
+        columns.push(("Scan Time", 10));
+    }
+    let data_width: u16 = columns.iter().skip(1).map(|(_, w)| w + 1).sum();
+    columns[0].1 = area
+        .width
+        .saturating_sub(2 + HIGHLIGHT_SYMBOL.len() as u16 + data_width);
+
+    let header_cells = columns.iter().map(|(h, w)| {
+        // Over its right-aligned figures
+        let heading = match *h {
+            "Files" => format!("{:>width$}", h, width = *w as usize),
+            _ => h.to_string(),
+        };
+        Cell::from(heading).style(Style::default().add_modifier(Modifier::BOLD))
+    });
 
     let header = Row::new(header_cells)
         .style(Style::default().add_modifier(Modifier::REVERSED))
         .bottom_margin(1);
 
-    let items: Vec<Row> = app
-        .current_node
-        .children
-        .iter()
-        .enumerate()
-        .map(|(i, child)| {
-            let is_selected = app.selected == i;
-            let style = if is_selected {
-                Style::default().add_modifier(Modifier::REVERSED)
+    let mut items = Vec::new();
+    let mut targets = Vec::new();
+    let mut selected_row = None;
+    let mut title = None;
+    if let Some(view) = &app.flat_view {
+        // Views spanning the whole scan are measured against the root
+        let whole = if view.base.as_os_str().is_empty() {
+            app.stack[0].size
+        } else {
+            app.current_node.size
+        };
+        let largest = largest_shown(app, &view.entries);
+        for (i, entry) in view.entries.iter().enumerate() {
+            let mut label = entry
+                .path
+                .strip_prefix(&view.base)
+                .unwrap_or(&entry.path)
+                .display()
+                .to_string();
+            if let Some(link) = entry.hardlink {
+                label.push_str(&format!("  [inode {}, {} links", link.inode, link.links));
+                if !link.counted {
+                    label.push_str(", counted under another name");
+                }
+                label.push(']');
+            }
+            if let Some(note) = view.notes.get(&entry.path) {
+                label.push_str(&format!("  ({})", note));
+            }
+            items.push(entry_cells(app, entry, label, whole, largest, share_width));
+            targets.push(Some(i));
+        }
+        selected_row = Some(view.selected);
+        title = Some(format!(" {} ({}) ", view.title, view.entries.len()));
+    } else {
+        let mut limits = Vec::new();
+        if let Some(query) = &app.filter {
+            limits.push(format!(
+                "Names containing \"{}\" ({} to edit)",
+                query,
+                first_key(app, Action::Search)
+            ));
+        }
+        if let Some(min) = app.min_size {
+            limits.push(format!(
+                "At least {} ({} to change)",
+                app.format_size(min),
+                first_key(app, Action::CycleMinSize)
+            ));
+        }
+        if app.rollup_small {
+            limits.push(format!(
+                "Entries under 1% rolled up ({} to list them)",
+                first_key(app, Action::ToggleRollup)
+            ));
+        }
+        if !limits.is_empty() {
+            let inverted = if app.filter_inverted() {
+                "Hidden by: "
             } else {
-                Style::default()
+                ""
             };
+            title = Some(format!(" {}{} ", inverted, limits.join(" | ")));
+        }
+        let largest = largest_shown(app, &app.current_node.children);
+        // Expanded directories interleave their children, so the selected
+        // child isn't necessarily the selected table row
+        for (i, child) in app.current_node.children.iter().enumerate() {
+            if !app.matches_filter(child) {
+                continue;
+            }
+            if i == app.selected {
+                selected_row = Some(items.len());
+            }
+            let whole = app.current_node.size;
+            items.push(entry_cells(
+                app,
+                child,
+                chain_label(app, child),
+                whole,
+                largest,
+                share_width,
+            ));
+            targets.push(Some(i));
+            if let Some(peeked) = app.expanded_children(child) {
+                let last = peeked.len().saturating_sub(1);
+                for (j, grandchild) in peeked.into_iter().enumerate() {
+                    let connector = if j == last {
+                        app.glyphs.tree_last
+                    } else {
+                        app.glyphs.tree_branch
+                    };
+                    let label = format!("  {}{}", connector, entry_name(grandchild));
+                    items.push(entry_cells(
+                        app,
+                        grandchild,
+                        label,
+                        whole,
+                        largest,
+                        share_width,
+                    ));
+                    targets.push(Some(i));
+                }
+            }
+        }
+        // Sized like a child, though there's nothing to open
+        let rolled_up = app.rolled_up();
+        if rolled_up.count > 0 {
+            let mut cells = vec![String::new(); columns.len()];
+            cells[0] = format!(
+                "{} {} other {}",
+                app.glyphs.ellipsis,
+                rolled_up.count,
+                if rolled_up.count == 1 {
+                    "item"
+                } else {
+                    "items"
+                }
+            );
+            cells[1] = app.format_size(rolled_up.size);
+            cells[SHARE_COLUMN] =
+                share_bar(app, rolled_up.size, app.current_node.size, share_width);
+            items.push((cells, Style::default().fg(Color::DarkGray)));
+            targets.push(None);
+        }
+        let hidden = app.hidden_by_size();
+        if hidden > 0 {
+            let mut cells = vec![String::new(); columns.len()];
+            cells[0] = format!(
+                "({} {} under {} hidden)",
+                hidden,
+                if hidden == 1 { "item" } else { "items" },
+                app.format_size(app.min_size.unwrap_or_default())
+            );
+            items.push((cells, Style::default().fg(Color::DarkGray)));
+            targets.push(None);
+        }
+    }
 
-            let name = child
-                .path
-                .file_name()
-                .map(|s| s.to_string_lossy().to_string())
-                .unwrap_or_else(|| "/".to_string());
-
-            let name_style = if child.is_dir {
-                Style::default()
-                    .fg(Color::Blue)
-                    .add_modifier(Modifier::BOLD)
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(title.unwrap_or_default());
+    let highlight_style = Style::default()
+        .add_modifier(Modifier::REVERSED)
+        .add_modifier(Modifier::BOLD);
+
+    let inner = block.inner(area);
+    app.list_layout.targets = targets;
+    if items.is_empty() {
+        app.list_layout.area = Rect::default();
+        // Say why there's nothing rather than leave a blank table; with
+        // children present, the filter hid them all
+        let notice = if app.flat_view.is_some() || !app.current_node.children.is_empty() {
+            "Nothing matches here.".to_string()
+        } else if let Some(err) = app.read_error() {
+            format!("This directory couldn't be read: {}", err.message)
+        } else {
+            if app.scan_options.one_file_system && app.current_node.mount_point {
+                "(another filesystem, not scanned with --one-file-system)".to_string()
+            } else if app.scan_options.max_depth.is_some() && app.current_node.inodes > 1 {
+                "(contents below --max-depth)".to_string()
             } else {
-                Style::default()
-            };
+                "(empty directory)".to_string()
+            }
+        };
+        let paragraph = Paragraph::new(Span::styled(notice, Style::default().fg(Color::DarkGray)))
+            .alignment(Alignment::Center)
+            .block(block);
+        f.render_widget(paragraph, area);
+        return;
+    }
 
-            Row::new(vec![
-                name,
-                humansize::format_size(child.size, humansize::DECIMAL),
-            ])
-            .style(style)
-            .style(name_style)
-        })
+    let items_len = items.len();
+    if app.detailed {
+        // Name on its own line, figures on the next, so long names fit
+        let items: Vec<ListItem> = items
+            .into_iter()
+            .map(|(cells, style)| {
+                // Summary rows have no figures; keep the line for even rows
+                let details: Vec<String> = columns
+                    .iter()
+                    .zip(&cells)
+                    .skip(1)
+                    .filter(|(_, value)| !value.is_empty())
+                    .map(|((heading, _), value)| format!("{}: {}", heading, value.trim_start()))
+                    .collect();
+                ListItem::new(vec![
+                    Spans::from(Span::styled(cells[0].clone(), style)),
+                    Spans::from(Span::styled(
+                        format!("    {}", details.join(" | ")),
+                        Style::default().fg(Color::DarkGray),
+                    )),
+                ])
+            })
+            .collect();
+        let list = List::new(items)
+            .block(block)
+            .highlight_style(highlight_style)
+            .highlight_symbol(HIGHLIGHT_SYMBOL);
+        app.list_state.select(selected_row);
+        let layout = &mut app.list_layout;
+        layout.row_height = 2;
+        layout.area = inner;
+        layout.list_offset = first_row(
+            layout.list_offset,
+            selected_row.unwrap_or(0),
+            items_len,
+            inner.height / 2,
+        );
+        layout.offset = layout.list_offset;
+        f.render_stateful_widget(list, area, &mut app.list_state);
+        return;
+    }
+
+    let widths: Vec<Constraint> = columns
+        .iter()
+        .map(|(_, w)| Constraint::Length(*w))
         .collect();
+    // The row style colors the name; keep the bar in the plain text color
+    let bar_style = Style::default()
+        .fg(Color::Reset)
+        .remove_modifier(Modifier::BOLD);
+    let rows = items.into_iter().map(|(cells, style)| {
+        let cells = cells.into_iter().enumerate().map(|(i, text)| match i {
+            SHARE_COLUMN => Cell::from(text).style(bar_style),
+            _ => Cell::from(text),
+        });
+        Row::new(cells).style(style)
+    });
 
-    let table = Table::new(items)
+    let table = Table::new(rows)
         .header(header)
-        .block(Block::default().borders(Borders::ALL))
-        .highlight_style(Style::default().add_modifier(Modifier::BOLD))
-        .widths(&[Constraint::Percentage(70), Constraint::Percentage(30)]);
+        .block(block)
+        .highlight_style(highlight_style)
+        .highlight_symbol(HIGHLIGHT_SYMBOL)
+        .widths(&widths);
 
-    let mut state = TableState::default();
-    state.select(Some(app.selected));
-    f.render_stateful_widget(table, area, &mut state);
+    app.table_state.select(selected_row);
+    // Rows start below the header line and its margin
+    let layout = &mut app.list_layout;
+    let rows_area = Rect {
+        y: inner.y + 2,
+        height: inner.height.saturating_sub(2),
+        ..inner
+    };
+    layout.row_height = 1;
+    layout.area = rows_area;
+    layout.table_offset = first_row(
+        layout.table_offset,
+        selected_row.unwrap_or(0),
+        items_len,
+        rows_area.height,
+    );
+    layout.offset = layout.table_offset;
+    f.render_stateful_widget(table, area, &mut app.table_state);
 }
 
-fn draw_status_bar<B: Backend>(f: &mut Frame<B>, area: Rect, app: &App) {
-    let (file_count, dir_count) =
-        app.current_node
-            .children
+/// The first row a table or list shows, following the widgets' own
+/// scrolling: the view moves only as far as needed to keep `selected` on
+/// screen. Their scroll offset isn't readable, so it's mirrored here.
+fn first_row(previous: usize, selected: usize, rows: usize, fits: u16) -> usize {
+    let fits = (fits as usize).max(1);
+    let previous = previous.min(rows.saturating_sub(1));
+    if selected < previous {
+        selected
+    } else if selected >= previous + fits {
+        selected + 1 - fits
+    } else {
+        previous
+    }
+}
+
+fn entry_name(entry: &DirEntryInfo) -> String {
+    entry
+        .path
+        .file_name()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "/".to_string())
+}
+
+/// `entry`'s name, or the path down to the end of its chain when
+/// single-child chains are collapsed.
+fn chain_label(app: &App, entry: &DirEntryInfo) -> String {
+    let chain = app.chain(entry);
+    if chain.len() == 1 {
+        return entry_name(entry);
+    }
+    let names: Vec<String> = chain.into_iter().map(entry_name).collect();
+    names.join("/")
+}
+
+/// `size` as a share of `whole`, drawn as a bar filling `width` columns
+/// followed by the percentage.
+fn share_bar(app: &App, size: u64, whole: u64, width: u16) -> String {
+    let ratio = if whole == 0 {
+        0.0
+    } else {
+        (size as f64 / whole as f64).min(1.0)
+    };
+    let bar_width = usize::from(width).saturating_sub(5);
+    let full = (ratio * bar_width as f64).round() as usize;
+    format!(
+        "{}{} {:>3.0}%",
+        app.glyphs.bar_full.repeat(full),
+        app.glyphs.bar_empty.repeat(bar_width - full),
+        ratio * 100.0
+    )
+}
+
+/// The size an entry's row shows: its whole subtree, or with direct sizes
+/// only the files directly inside it.
+fn shown_size(app: &App, entry: &DirEntryInfo) -> u64 {
+    if app.direct_sizes {
+        entry.direct_size()
+    } else {
+        entry.size
+    }
+}
+
+fn largest_shown(app: &App, entries: &[DirEntryInfo]) -> u64 {
+    entries
+        .iter()
+        .map(|e| shown_size(app, e))
+        .max()
+        .unwrap_or(0)
+}
+
+/// Cell texts for `entry`, in column order, and the style of its row.
+fn entry_cells(
+    app: &App,
+    entry: &DirEntryInfo,
+    label: String,
+    whole: u64,
+    largest: u64,
+    share_width: u16,
+) -> (Vec<String>, Style) {
+    // Flag sparse or compressed entries, whose logical size overstates usage
+    let badge = entry
+        .allocation_ratio()
+        .map(|ratio| format!("  ({:.0}% on disk)", ratio * 100.0))
+        .unwrap_or_default();
+
+    let mount = if entry.mount_point {
+        format!("  {} mount", app.glyphs.mount)
+    } else if entry.symlink_loop {
+        format!("  {} symlink loop", app.glyphs.symlink_loop)
+    } else {
+        String::new()
+    };
+
+    let size = shown_size(app, entry);
+    let name_style = app.theme.row_style(&RowTraits {
+        is_dir: entry.is_dir,
+        empty: entry.size == 0,
+        recent: app.is_recent(entry),
+        over_warning: app.is_over_warning(entry),
+        fraction: if largest == 0 {
+            0.0
+        } else {
+            size as f64 / largest as f64
+        },
+    });
+    let mut cells = vec![
+        format!("{}{}{}", label, mount, badge),
+        app.format_size(size),
+        share_bar(app, size, whole, share_width),
+    ];
+    if app.show_rounded {
+        cells.push(app.format_size(entry.rounded_size));
+    }
+    if app.show_inodes {
+        cells.push(entry.inodes.to_string());
+    }
+    if app.shows_avg_size() {
+        cells.push(app.format_size(entry.average_file_size()));
+    }
+    if app.shows_file_count() {
+        // Right-aligned so the magnitudes line up
+        let width = FILES_WIDTH as usize;
+        cells.push(format!("{:>width$}", entry.file_count));
+    }
+    if app.shows_modified() {
+        // Directories show their newest content, which is what sorts them
+        cells.push(entry.newest_modified.map(format_age).unwrap_or_default());
+    }
+    if app.show_scan_times {
+        // Only the root's child directories are timed
+        cells.push(
+            app.scan_times
+                .get(&entry.path)
+                .filter(|_| entry.is_dir)
+                .map(|t| format!("{:.1}s", t.as_secs_f64()))
+                .unwrap_or_default(),
+        );
+    }
+
+    (cells, name_style)
+}
+
+/// A box centered in `area` just big enough for `lines` and a border.
+fn popup_area(area: Rect, lines: &[String]) -> Rect {
+    let width = lines
+        .iter()
+        .map(|l| l.chars().count() as u16 + 4)
+        .max()
+        .unwrap_or(0)
+        .min(area.width);
+    let height = (lines.len() as u16 + 2).min(area.height);
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
+
+/// How a key is shown in the help.
+fn key_label(app: &App, key: KeyCode) -> String {
+    let g = &app.glyphs;
+    match key {
+        KeyCode::Char(' ') => "Space".to_string(),
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::Up => g.up.to_string(),
+        KeyCode::Down => g.down.to_string(),
+        KeyCode::Left => g.left.to_string(),
+        KeyCode::Right => g.right.to_string(),
+        KeyCode::PageUp => "PgUp".to_string(),
+        KeyCode::PageDown => "PgDn".to_string(),
+        KeyCode::F(n) => format!("F{}", n),
+        other => format!("{:?}", other),
+    }
+}
+
+/// The label of the first key bound to `action`, for hints that name one.
+fn first_key(app: &App, action: Action) -> String {
+    match app.keymap.keys_for(action).first() {
+        Some(&key) => key_label(app, key),
+        None => UNBOUND.to_string(),
+    }
+}
+
+/// Every key bound to `action`, e.g. `↑/k`.
+fn key_hint(app: &App, action: Action) -> String {
+    let keys: Vec<String> = app
+        .keymap
+        .keys_for(action)
+        .into_iter()
+        .map(|key| key_label(app, key))
+        .collect();
+    if keys.is_empty() {
+        UNBOUND.to_string()
+    } else {
+        keys.join("/")
+    }
+}
+
+/// Shown for an action that `keys.toml` left without a key.
+const UNBOUND: &str = "(unbound)";
+
+/// Lists every action with the keys currently bound to it, from the same
+/// table the bindings come from. Goes to two columns when one won't fit.
+fn draw_help<B: Backend>(f: &mut Frame<B>, area: Rect, app: &App) {
+    let entries: Vec<(String, &str)> = BINDINGS
+        .iter()
+        .map(|b| (key_hint(app, b.action), b.help))
+        .collect();
+    let key_width = entries
+        .iter()
+        .map(|(k, _)| k.chars().count())
+        .max()
+        .unwrap_or(0);
+    let mut lines: Vec<String> = entries
+        .iter()
+        .map(|(keys, help)| format!("{:<width$}  {}", keys, help, width = key_width))
+        .collect();
+    if lines.len() + 2 > usize::from(area.height) {
+        let rows = lines.len().div_ceil(2);
+        let column_width = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+        let (left, right) = lines.split_at(rows);
+        lines = left
             .iter()
-            .fold((0, 0), |(files, dirs), child| {
-                if child.is_dir {
-                    (files, dirs + 1)
-                } else {
-                    (files + 1, dirs)
-                }
-            });
+            .enumerate()
+            .map(|(i, l)| match right.get(i) {
+                Some(r) => format!("{:<width$}    {}", l, r, width = column_width),
+                None => l.clone(),
+            })
+            .collect();
+    }
+
+    let popup = popup_area(area, &lines);
+    let text: Vec<Spans> = lines
+        .into_iter()
+        .map(|l| Spans::from(format!(" {}", l)))
+        .collect();
+    let paragraph = Paragraph::new(text).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(" Keys (any key to close) "),
+    );
+    f.render_widget(Clear, popup);
+    f.render_widget(paragraph, popup);
+}
 
-    let status = format!(
-        "↑/k/↓/j: Navigate | →/Enter: Open | ←/Backspace: Go Back | s: Toggle Sort | Files: {} | Dirs: {} | Total: {}",
-        file_count,
-        dir_count,
-        humansize::format_size(app.current_node.size, humansize::DECIMAL)
+/// Asks whether to really delete the entry in `confirm_delete`.
+fn draw_delete_confirmation<B: Backend>(f: &mut Frame<B>, area: Rect, app: &App) {
+    let Some(entry) = app
+        .confirm_delete
+        .as_ref()
+        .and_then(|path| app.current_node.children.iter().find(|c| &c.path == path))
+    else {
+        return;
+    };
+    let what = if entry.is_dir {
+        "directory and everything in it"
+    } else {
+        "file"
+    };
+    // The data stays for as long as another name points to it
+    let freed = if entry.hardlink.is_some() {
+        "Its other names keep its space, so nothing is freed.".to_string()
+    } else {
+        format!("{} will be freed.", app.format_size(entry.size))
+    };
+    let lines = vec![
+        format!("Delete this {}?", what),
+        entry.path.display().to_string(),
+        freed,
+        String::new(),
+        "y to delete, any other key to cancel".to_string(),
+    ];
+    let popup = popup_area(area, &lines);
+    let text: Vec<Spans> = lines
+        .into_iter()
+        .map(|l| Spans::from(format!(" {}", l)))
+        .collect();
+    let paragraph = Paragraph::new(text).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::Red))
+            .title(" Delete "),
     );
+    f.render_widget(Clear, popup);
+    f.render_widget(paragraph, popup);
+}
+
+/// Colors the treemap cycles through, so neighbouring tiles differ.
+const TILE_COLORS: [Color; 6] = [
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::Green,
+    Color::Yellow,
+    Color::Red,
+];
+
+/// One entry's rectangle in the treemap, labelled from the top left.
+struct Tile {
+    area: Rect,
+    lines: Vec<String>,
+    style: Style,
+}
 
+/// Fills each tile with its style and writes as much of its label as fits.
+struct Tiles(Vec<Tile>);
+
+impl Widget for Tiles {
+    fn render(self, _area: Rect, buf: &mut Buffer) {
+        for tile in self.0 {
+            buf.set_style(tile.area, tile.style);
+            for (line, y) in tile.lines.iter().zip(tile.area.top()..tile.area.bottom()) {
+                buf.set_stringn(tile.area.x, y, line, tile.area.width as usize, tile.style);
+            }
+        }
+    }
+}
+
+/// The current directory's listed children as a squarified treemap, each
+/// tile sized by its share of the directory.
+fn draw_treemap<B: Backend>(f: &mut Frame<B>, area: Rect, app: &App) {
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(" Treemap (v to hide) ");
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+
+    // The layout wants the largest first, whatever the listing's order
+    let children = &app.current_node.children;
+    let mut shown = app.visible_children();
+    shown.sort_by_key(|&i| Reverse(shown_size(app, &children[i])));
+    let sizes: Vec<u64> = shown
+        .iter()
+        .map(|&i| shown_size(app, &children[i]))
+        .collect();
+    let tiles = shown
+        .iter()
+        .zip(treemap::squarify(inner, &sizes))
+        .enumerate()
+        .filter(|(_, (_, area))| area.area() > 0)
+        .map(|(n, (&i, area))| {
+            let child = &children[i];
+            // Without colors, alternate plain and reversed tiles instead
+            let style = match app.theme {
+                Theme::Plain if n % 2 == 0 => Style::default().add_modifier(Modifier::REVERSED),
+                Theme::Plain => Style::default(),
+                _ => Style::default()
+                    .fg(Color::Black)
+                    .bg(TILE_COLORS[n % TILE_COLORS.len()]),
+            };
+            let style = match (i == app.selected, app.theme) {
+                (false, _) => style,
+                (true, Theme::Plain) => style.add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
+                (true, _) => style.add_modifier(Modifier::BOLD | Modifier::REVERSED),
+            };
+            Tile {
+                area,
+                lines: vec![entry_name(child), app.format_size(shown_size(app, child))],
+                style,
+            }
+        })
+        .collect();
+    f.render_widget(Tiles(tiles), inner);
+}
+
+/// Lists why the totals may be short, over the middle of the listing.
+fn draw_completeness<B: Backend>(f: &mut Frame<B>, area: Rect, app: &App) {
+    let mut lines = app.completeness.reasons();
+    if lines.is_empty() {
+        lines.push("Every entry was read; totals are exact.".to_string());
+    }
+    let popup = popup_area(area, &lines);
+    let text: Vec<Spans> = lines
+        .into_iter()
+        .map(|l| Spans::from(format!(" {}", l)))
+        .collect();
+    let paragraph =
+        Paragraph::new(text).block(Block::default().borders(Borders::ALL).title(format!(
+            " Totals ({} to close) ",
+            first_key(app, Action::ShowCompleteness)
+        )));
+    f.render_widget(Clear, popup);
+    f.render_widget(paragraph, popup);
+}
+
+fn draw_status_bar<B: Backend>(f: &mut Frame<B>, area: Rect, app: &App) {
+    if let Some(prompt) = &app.prompt {
+        let line = format!("{}{}", prompt.kind.label(), prompt.input);
+        let cursor_x = area.x + 1 + line.chars().count() as u16;
+        let prompt_bar = Paragraph::new(Span::raw(line)).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(" Enter: confirm | Esc: cancel "),
+        );
+        f.render_widget(prompt_bar, area);
+        f.set_cursor(cursor_x.min(area.right().saturating_sub(2)), area.y + 1);
+        return;
+    }
+
+    let g = &app.glyphs;
+    let navigate = format!(
+        "{}/{}",
+        key_hint(app, Action::MoveSelection(-1)),
+        key_hint(app, Action::MoveSelection(1))
+    );
+    let mut status = if let Some(view) = &app.flat_view {
+        format!(
+            "{}: Navigate | {}: Show in Tree | {}: Back | {}: {}",
+            navigate,
+            key_hint(app, Action::NavigateIn),
+            key_hint(app, Action::NavigateOut),
+            view.title,
+            view.entries.len()
+        )
+    } else {
+        // Everything below here, not just the listed children
+        let node = &app.current_node;
+        let file_count = node.file_count;
+        let dir_count = node.inodes.saturating_sub(node.file_count + 1);
+        format!(
+            "{}: Help | {}: Navigate | {}: Open | {}: Go Back | {}/{}: Sort by {} {} | Files: {} | Dirs: {} | Total: {}{} ({}) | Counts: {}",
+            first_key(app, Action::ToggleHelp),
+            navigate,
+            key_hint(app, Action::NavigateIn),
+            key_hint(app, Action::NavigateOut),
+            first_key(app, Action::ToggleSort),
+            first_key(app, Action::ToggleSortDirection),
+            app.sort_by.key.name(),
+            if app.sort_by.ascending { g.up } else { g.down },
+            file_count,
+            dir_count,
+            if app.completeness.is_exact() { "" } else { "at least " },
+            app.format_size(app.current_node.size),
+            if app.completeness.is_exact() {
+                "exact".to_string()
+            } else {
+                format!("{}: why", first_key(app, Action::ShowCompleteness))
+            },
+            app.scan_options.size_policy.describe()
+        )
+    };
+    if app.show_inodes && app.flat_view.is_none() {
+        status.push_str(&format!(" | Inodes: {}", app.current_node.inodes));
+        if let Some(free) = app.free_inodes {
+            status.push_str(&format!(" | Free inodes: {}", free));
+        }
+    }
+
+    if let Some(entry) = app.selected_entry() {
+        if let Some(ratio) = entry.allocation_ratio() {
+            status.push_str(&format!(
+                " | Selected: {} on disk of {} ({:.1}%)",
+                app.format_size(entry.allocated_size),
+                app.format_size(entry.size),
+                ratio * 100.0
+            ));
+        }
+    }
+
+    if !app.errors.is_empty() {
+        status = format!(
+            "{} {} error{} ({}: list) | {}",
+            g.warning,
+            app.errors.len(),
+            if app.errors.len() == 1 { "" } else { "s" },
+            first_key(app, Action::ShowErrors),
+            status
+        );
+    }
+    if app.filter_inverted() {
+        status = format!(
+            "Filter inverted ({}: undo) | {}",
+            first_key(app, Action::InvertFilter),
+            status
+        );
+    }
+    if let Some(scan) = &app.live_scan {
+        status = format!(
+            "Scanning{} {} files, {} | {}",
+            g.ellipsis,
+            format_count(scan.files),
+            app.format_size(scan.bytes),
+            status
+        );
+    }
+    if let Some(scan) = &app.pending_scan {
+        let frame = scan.started.elapsed().as_millis() / SPINNER_FRAME.as_millis();
+        let spinner = g.spinner[frame as usize % g.spinner.len()];
+        status = format!("{} {} | {}", spinner, scan.progress.message(), status);
+    }
+    if let Some(delete) = &app.pending_delete {
+        status = format!(
+            "Deleting {}{} {}/{} entries, {} freed (Esc to stop) | {}",
+            delete.path.display(),
+            g.ellipsis,
+            format_count(delete.removed),
+            format_count(delete.total),
+            app.format_size(delete.freed),
+            status
+        );
+    }
+    if let Some(message) = &app.message {
+        status = format!("{} | {}", message, status);
+    }
+
+    let style = if app.alert {
+        Style::default()
+            .fg(Color::White)
+            .bg(Color::Red)
+            .add_modifier(Modifier::BOLD)
+    } else {
+        Style::default()
+    };
     let status_bar =
-        Paragraph::new(Span::raw(status)).block(Block::default().borders(Borders::ALL));
+        Paragraph::new(Span::styled(status, style)).block(Block::default().borders(Borders::ALL));
 
     f.render_widget(status_bar, area);
 }
+
+/// The current directory as linear text, one entry per line, for screen
+/// readers and other tools that can't follow the table layout.
+pub fn plain_listing(app: &App) -> Vec<String> {
+    let node = &app.current_node;
+    let shown: Vec<&DirEntryInfo> = node
+        .children
+        .iter()
+        .filter(|c| app.matches_filter(c))
+        .collect();
+    // Say how many a filter hides, so the count matches the lines below
+    let entries = if shown.len() == node.children.len() {
+        format!("{} entries", shown.len())
+    } else {
+        format!("{} of {} entries shown", shown.len(), node.children.len())
+    };
+    let mut lines = vec![format!(
+        "{}: {}, {} total",
+        node.path.display(),
+        entries,
+        app.format_size(node.size)
+    )];
+    for child in shown {
+        let pct = if node.size == 0 {
+            0.0
+        } else {
+            child.size as f64 / node.size as f64 * 100.0
+        };
+        lines.push(format!(
+            "{}, {}, {}, {:.1} percent",
+            entry_name(child),
+            if child.is_dir { "directory" } else { "file" },
+            app.format_size(child.size),
+            pct
+        ));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{dir_entry, file_entry};
+    use crate::ui::glyphs::ASCII;
+    use crate::ui::keymap::Keymap;
+
+    fn lines(widths: &[usize]) -> Vec<String> {
+        widths.iter().map(|&w| "x".repeat(w)).collect()
+    }
+
+    #[test]
+    fn popups_are_centered_and_fit_their_lines() {
+        let area = Rect::new(0, 0, 80, 24);
+        let popup = popup_area(area, &lines(&[10, 26, 4]));
+        assert_eq!(popup, Rect::new(25, 9, 30, 5));
+
+        // Odd leftovers go below and to the right
+        let popup = popup_area(Rect::new(10, 5, 81, 25), &lines(&[10]));
+        assert_eq!(popup, Rect::new(43, 16, 14, 3));
+    }
+
+    #[test]
+    fn popups_shrink_to_a_small_area() {
+        let area = Rect::new(2, 1, 20, 6);
+        let popup = popup_area(area, &lines(&[50; 10]));
+        assert_eq!(popup, area);
+    }
+
+    fn segments(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn breadcrumb_collapses_the_middle_of_deep_paths() {
+        let g = &ASCII;
+        let path = segments(&[
+            "/",
+            "home",
+            "alice",
+            "projects",
+            "rust",
+            "disk-usage",
+            "src",
+            "ui",
+        ]);
+
+        let shown = breadcrumb(&path, 40, g);
+        assert_eq!(shown, ["/", "...", "rust", "disk-usage", "src", "ui"]);
+        assert!(shown.join(g.crumb_separator).chars().count() <= 40);
+
+        // Too narrow for the root: only the current directory is left
+        assert_eq!(breadcrumb(&path, 8, g), ["...", "ui"]);
+        assert_eq!(breadcrumb(&path, 80, g), path);
+    }
+
+    #[test]
+    fn plain_listing_counts_the_entries_it_shows() {
+        let tree = dir_entry(
+            "/r",
+            vec![
+                file_entry("/r/apple", 1),
+                file_entry("/r/banana", 3),
+                file_entry("/r/cherry", 4),
+            ],
+        );
+        let mut app = App::new(tree);
+        let listing = plain_listing(&app);
+        assert_eq!(listing[0], "/r: 3 entries, 8 B total");
+        assert_eq!(listing.len(), 4);
+
+        app.set_filter(Some("an".to_string()));
+        let listing = plain_listing(&app);
+        assert_eq!(listing[0], "/r: 1 of 3 entries shown, 8 B total");
+        assert_eq!(listing[1..], ["banana, file, 3 B, 37.5 percent"]);
+    }
+
+    #[test]
+    fn key_hints_follow_remapped_keys() {
+        let mut app = App::new(dir_entry("/r", vec![]));
+        app.glyphs = ASCII;
+        assert_eq!(first_key(&app, Action::Quit), "q");
+        assert_eq!(key_hint(&app, Action::MoveSelection(-1)), "Up/k");
+
+        app.keymap = Keymap::parse("quit = \"x\"\nup = \"u\"").unwrap();
+        assert_eq!(first_key(&app, Action::Quit), "x");
+        assert_eq!(key_hint(&app, Action::MoveSelection(-1)), "u");
+
+        app.keymap = Keymap::parse("help = []").unwrap();
+        assert_eq!(first_key(&app, Action::ToggleHelp), "(unbound)");
+    }
+}